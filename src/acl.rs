@@ -0,0 +1,339 @@
+//! Packet filter rules of the server data path.
+//!
+//! A rule reads `allow|deny [from <net>] [to <net>] [proto tcp|udp|icmp] [port <n>[-<m>]]`,
+//! the first matching rule decides, the default action otherwise. A rule matches
+//! a flow in either direction, the port is that of the `to` end.
+
+use crate::error::Error;
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Allow,
+    Deny,
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "deny" => Ok(Self::Deny),
+            _ => Err(Error::InvalidArg(format!("invalid acl action {:?}", s))),
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Allow => "allow",
+            Self::Deny => "deny",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Proto {
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+impl Proto {
+    fn matches(&self, proto: u8) -> bool {
+        match self {
+            Self::Tcp => proto == 6,
+            Self::Udp => proto == 17,
+            Self::Icmp => proto == 1 || proto == 58,
+        }
+    }
+}
+
+impl FromStr for Proto {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(Self::Tcp),
+            "udp" => Ok(Self::Udp),
+            "icmp" => Ok(Self::Icmp),
+            _ => Err(Error::InvalidArg(format!("invalid acl proto {:?}", s))),
+        }
+    }
+}
+
+impl Display for Proto {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+            Self::Icmp => "icmp",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub action: Action,
+    pub from: Option<IpNet>,
+    pub to: Option<IpNet>,
+    pub proto: Option<Proto>,
+    pub ports: Option<(u16, u16)>,
+}
+
+impl Rule {
+    pub fn matches(&self, flow: &Flow) -> bool {
+        self.proto.is_none_or(|v| v.matches(flow.proto))
+            && (self.matches_way(&flow.src, &flow.dst, flow.dport)
+                || self.matches_way(&flow.dst, &flow.src, flow.sport))
+    }
+
+    // Whether the rule matches the way from `src` to `dst`, `port` being the one at `dst`.
+    fn matches_way(&self, src: &IpAddr, dst: &IpAddr, port: Option<u16>) -> bool {
+        self.from.is_none_or(|v| v.contains(src))
+            && self.to.is_none_or(|v| v.contains(dst))
+            && self
+                .ports
+                .is_none_or(|(min, max)| port.is_some_and(|v| (min..=max).contains(&v)))
+    }
+}
+
+fn parse_net(s: &str) -> Result<IpNet, Error> {
+    s.parse()
+        .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| Error::InvalidArg(format!("invalid acl network {:?}", s)))
+}
+
+fn parse_ports(s: &str) -> Result<(u16, u16), Error> {
+    let invalid = || Error::InvalidArg(format!("invalid acl port {:?}", s));
+    let (min, max) = s.split_once('-').unwrap_or((s, s));
+    let (min, max) = (
+        min.parse().map_err(|_| invalid())?,
+        max.parse().map_err(|_| invalid())?,
+    );
+    if min > max {
+        return Err(invalid());
+    }
+    Ok((min, max))
+}
+
+impl FromStr for Rule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let mut rule = Rule {
+            action: words.next().unwrap_or_default().parse()?,
+            from: None,
+            to: None,
+            proto: None,
+            ports: None,
+        };
+
+        while let Some(key) = words.next() {
+            let value = words
+                .next()
+                .ok_or_else(|| Error::InvalidArg(format!("acl {:?} without value", key)))?;
+            match key {
+                "from" => rule.from = Some(parse_net(value)?),
+                "to" => rule.to = Some(parse_net(value)?),
+                "proto" => rule.proto = Some(value.parse()?),
+                "port" => rule.ports = Some(parse_ports(value)?),
+                _ => return Err(Error::InvalidArg(format!("invalid acl key {:?}", key))),
+            }
+        }
+
+        Ok(rule)
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.action)?;
+        if let Some(from) = self.from {
+            write!(f, " from {}", from)?;
+        }
+        if let Some(to) = self.to {
+            write!(f, " to {}", to)?;
+        }
+        if let Some(proto) = self.proto {
+            write!(f, " proto {}", proto)?;
+        }
+        match self.ports {
+            Some((min, max)) if min == max => write!(f, " port {}", min)?,
+            Some((min, max)) => write!(f, " port {}-{}", min, max)?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// Addresses, protocol and ports of an IP packet.
+#[derive(Debug, PartialEq)]
+pub struct Flow {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub proto: u8,
+    pub sport: Option<u16>,
+    pub dport: Option<u16>,
+}
+
+impl Flow {
+    pub fn parse(pkt: &[u8]) -> Result<Self, Error> {
+        let (src, dst, proto, payload) = match pkt.first().map(|v| v >> 4) {
+            Some(4) if pkt.len() >= 20 => {
+                let ihl = (pkt[0] & 0x0f) as usize * 4;
+                if ihl < 20 {
+                    return Err(Error::InvalidPacket);
+                }
+                let fragment = u16::from_be_bytes([pkt[6], pkt[7]]) & 0x1fff != 0;
+                let src: [u8; 4] = pkt[12..16].try_into().unwrap();
+                let dst: [u8; 4] = pkt[16..20].try_into().unwrap();
+                // only the first fragment has the ports
+                let payload = pkt.get(ihl..).filter(|_| !fragment);
+                (src.into(), dst.into(), pkt[9], payload)
+            }
+            Some(6) if pkt.len() >= 40 => {
+                let src: [u8; 16] = pkt[8..24].try_into().unwrap();
+                let dst: [u8; 16] = pkt[24..40].try_into().unwrap();
+                let (proto, payload) = upper_layer(pkt)?;
+                (src.into(), dst.into(), proto, payload)
+            }
+            _ => return Err(Error::InvalidPacket),
+        };
+
+        let port = |at: usize| match proto {
+            6 | 17 => payload
+                .and_then(|v| v.get(at..at + 2))
+                .map(|v| u16::from_be_bytes([v[0], v[1]])),
+            _ => None,
+        };
+
+        Ok(Self {
+            src,
+            dst,
+            proto,
+            sport: port(0),
+            dport: port(2),
+        })
+    }
+}
+
+// Protocol and payload of an IPv6 packet after the hop-by-hop, routing, destination
+// options and fragment headers. Only the first fragment has the ports.
+fn upper_layer(pkt: &[u8]) -> Result<(u8, Option<&[u8]>), Error> {
+    let (mut next, mut at) = (pkt[6], 40);
+    loop {
+        match next {
+            0 | 43 | 60 => {
+                let header = pkt.get(at..at + 2).ok_or(Error::InvalidPacket)?;
+                next = header[0];
+                at += (header[1] as usize + 1) * 8;
+            }
+            44 => {
+                let header = pkt.get(at..at + 8).ok_or(Error::InvalidPacket)?;
+                next = header[0];
+                at += 8;
+                if u16::from_be_bytes([header[2], header[3]]) & 0xfff8 != 0 {
+                    return Ok((next, None));
+                }
+            }
+            proto => return Ok((proto, pkt.get(at..))),
+        }
+    }
+}
+
+/// Action of the first rule matching the flow, and the rule.
+pub fn check<'a>(rules: &'a [Rule], default: Action, flow: &Flow) -> (Action, Option<&'a Rule>) {
+    rules
+        .iter()
+        .find(|v| v.matches(flow))
+        .map_or((default, None), |v| (v.action, Some(v)))
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    #[test]
+    fn test() {
+        let rules: Vec<Rule> = [
+            "allow from 10.0.0.2 to 192.168.0.0/16 proto tcp port 22",
+            "deny to 192.168.0.0/16",
+            "allow proto udp port 53-54",
+        ]
+        .iter()
+        .map(|v| v.parse().unwrap())
+        .collect();
+        assert_eq!(
+            rules[0].to_string(),
+            "allow from 10.0.0.2/32 to 192.168.0.0/16 proto tcp port 22"
+        );
+        assert!("allow port".parse::<Rule>().is_err());
+        assert!("allow port 54-53".parse::<Rule>().is_err());
+        assert!("reject".parse::<Rule>().is_err());
+
+        // TCP from 10.0.0.2:1234 to 192.168.1.1:22
+        let mut pkt = vec![0x45, 0, 0, 40, 0, 0, 0x40, 0, 64, 6, 0, 0];
+        pkt.extend_from_slice(&[10, 0, 0, 2, 192, 168, 1, 1]);
+        pkt.extend_from_slice(&[0x04, 0xd2, 0, 22]);
+        let flow = Flow::parse(&pkt).unwrap();
+        assert_eq!((flow.sport, flow.dport), (Some(1234), Some(22)));
+        assert_eq!(
+            check(&rules, Action::Deny, &flow),
+            (Action::Allow, Some(&rules[0]))
+        );
+
+        // the reply
+        let mut reply = pkt.clone();
+        reply[12..16].copy_from_slice(&pkt[16..20]);
+        reply[16..20].copy_from_slice(&pkt[12..16]);
+        reply[20..24].copy_from_slice(&[0, 22, 0x04, 0xd2]);
+        let flow = Flow::parse(&reply).unwrap();
+        assert_eq!(
+            check(&rules, Action::Deny, &flow),
+            (Action::Allow, Some(&rules[0]))
+        );
+
+        pkt[15] = 3;
+        let flow = Flow::parse(&pkt).unwrap();
+        assert_eq!(
+            check(&rules, Action::Allow, &flow),
+            (Action::Deny, Some(&rules[1]))
+        );
+
+        pkt[9] = 17;
+        pkt[16] = 8;
+        pkt[23] = 53;
+        let flow = Flow::parse(&pkt).unwrap();
+        assert_eq!(check(&rules, Action::Deny, &flow).0, Action::Allow);
+        pkt[23] = 80;
+        let flow = Flow::parse(&pkt).unwrap();
+        assert_eq!(check(&rules, Action::Deny, &flow), (Action::Deny, None));
+
+        assert!(Flow::parse(&pkt[..16]).is_err());
+        pkt[0] = 0x44;
+        assert!(Flow::parse(&pkt).is_err());
+
+        // TCP to port 22 behind hop-by-hop and fragment headers
+        let mut pkt = vec![0x60, 0, 0, 0, 0, 28, 0, 64];
+        pkt.extend_from_slice(&[0xfd; 32]);
+        pkt.extend_from_slice(&[44, 0, 0, 0, 0, 0, 0, 0]);
+        pkt.extend_from_slice(&[6, 0, 0, 0, 0, 0, 0, 1]);
+        pkt.extend_from_slice(&[0x04, 0xd2, 0, 22]);
+        let flow = Flow::parse(&pkt).unwrap();
+        assert_eq!((flow.proto, flow.dport), (6, Some(22)));
+        // a later fragment
+        pkt[51] = 0x08;
+        assert_eq!(Flow::parse(&pkt).unwrap().dport, None);
+        assert!(Flow::parse(&pkt[..50]).is_err());
+    }
+}
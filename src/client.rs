@@ -1,24 +1,32 @@
-use crate::config::Config;
+use crate::config::{Config, Diff, PmtuPolicy};
+use crate::dns::Dns;
+use crate::fragment::Fragments;
+use crate::mss;
+use crate::netconf::{self, Netlink, Route};
+use crate::pmtu::{self, Discovery};
 use crate::poll;
-use crate::util::source_ip;
+use crate::util::{source_ip, source_mac};
 use crate::Runtime;
 use crate::{
-    msg::{Builder, IpDataKind, IpDataPacket, MsgBuilder, MsgPacket, Op},
+    msg::{
+        Builder, EchoExt, EchoPacket, FragmentPacket, IpDataBuilder, IpDataKind, IpDataPacket,
+        MsgBuilder, MsgPacket, Op, MSG_HEADER_SIZE,
+    },
     state::State,
+    stats::{Fault, Faults},
     util::{choose_bind_addr, pretty_duration},
     Socket,
 };
+use ipnet::IpNet;
 use log::{debug, info, trace, warn};
 use nix::unistd::{read, write};
 use size::Size;
 use std::cell::RefCell;
 use std::fmt::Formatter;
-use std::io::{Read, Write};
 use std::mem::MaybeUninit;
+use std::net::{IpAddr, SocketAddr};
 use std::os::fd::OwnedFd;
-use std::os::unix::io::FromRawFd;
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::os::unix::net::UnixStream;
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
@@ -29,6 +37,12 @@ pub struct Client {
     pub(crate) rt: Runtime,
     pub(crate) state: RefCell<State>,
     pub(crate) server_index: RefCell<usize>,
+    pub(crate) server_route: RefCell<Option<Route>>,
+    pub(crate) dns: RefCell<Dns>,
+    pub(crate) pushed_routes: RefCell<Vec<Route>>,
+    pub(crate) faults: Faults,
+    pub(crate) fragments: RefCell<Fragments>,
+    pub(crate) pmtu: RefCell<Option<Discovery>>,
 }
 
 impl Client {
@@ -38,17 +52,26 @@ impl Client {
             rt,
             state: Default::default(),
             server_index: Default::default(),
+            server_route: Default::default(),
+            dns: Default::default(),
+            pushed_routes: Default::default(),
+            faults: Default::default(),
+            fragments: Default::default(),
+            pmtu: Default::default(),
         })
     }
 
     pub fn run(self) -> Result<()> {
-        let current_server = self.get_current_server_addr();
-        self.connect(current_server.as_str());
+        let control_fd = self.rt.control_fd.as_ref().map(|v| v.as_raw_fd());
+        let exit_signal = self.rt.exit_signal.as_ref().map(|v| v.as_raw_fd());
+        let reload_signal = self.rt.reload_signal.as_ref().map(|v| v.as_raw_fd());
+        let name = self.rt.tun_name.clone().unwrap_or_default();
+
         poll::poll(
-            self.tun().as_raw_fd(),
-            self.rt.control_fd.as_ref().map(|v| v.as_raw_fd()),
-            self.rt.exit_signal.as_ref().map(|v| v.as_raw_fd()),
-            self,
+            &mut [(name, Box::new(self) as Box<dyn poll::Reactor>)],
+            control_fd,
+            exit_signal,
+            reload_signal,
         )
     }
 
@@ -60,6 +83,22 @@ impl Client {
         &self.rt.tun_fd
     }
 
+    // Sends to the server, a failure is counted.
+    fn send(&self, s: &Socket, buf: &[u8]) {
+        if let Err(e) = s.send(buf) {
+            trace!("send to server fail. {:?}", e);
+            self.faults.add(Fault::Send);
+        }
+    }
+
+    // Writes to the tun interface, a failure is counted.
+    fn write_tun(&self, pkt: &[u8]) {
+        if let Err(e) = write(self.tun(), pkt) {
+            trace!("write tun fail. {:?}", e);
+            self.faults.add(Fault::TunWrite);
+        }
+    }
+
     fn get_current_server_addr(&self) -> String {
         let idx = *self.server_index.borrow();
         let server_addrs = self.config.get_server_addrs().unwrap();
@@ -107,38 +146,151 @@ impl Client {
 
         if let Ok(peer_addr) = s.peer_addr() {
             info!("connected to {:}", peer_addr);
+            self.update_server_route(peer_addr.ip());
+
+            // the path to a new server is searched from scratch
+            if self.config.pmtu_discovery != PmtuPolicy::Off {
+                *self.pmtu.borrow_mut() = Some(Discovery::new(&peer_addr));
+            }
         };
     }
 
+    // In full tunnel mode without fwmark, the server is routed outside the tunnel
+    // through the gateway that was used before the default routes were added. Not
+    // needed if the tunnel is in another network namespace than the socket.
+    fn update_server_route(&self, server: IpAddr) {
+        if !self.config.is_full_tunnel()
+            || self.config.fwmark.is_some()
+            || self.config.netns.is_some()
+        {
+            return;
+        }
+
+        let dst = IpNet::from(server);
+        if self
+            .server_route
+            .borrow()
+            .as_ref()
+            .is_some_and(|r| r.dst == dst)
+        {
+            return;
+        }
+
+        let result = Netlink::new().and_then(|nl| {
+            if let Some(old) = self.server_route.borrow_mut().take() {
+                let _ = nl.del_route(&old).inspect_err(|e| warn!("{}", e));
+            }
+
+            let tun = self.rt.tun_name.as_deref();
+            let mut via = nl.get_route(server)?;
+            if via.dev.is_none() || via.dev.as_deref() == tun {
+                via = nl
+                    .default_routes(server.is_ipv6())?
+                    .into_iter()
+                    .find(|r| r.dev.is_some() && r.dev.as_deref() != tun)
+                    .ok_or_else(|| crate::Error::NoRoute(server.to_string()))?;
+            }
+
+            let route = Route {
+                dst,
+                gateway: via.gateway,
+                dev: via.dev,
+                table: None,
+                metric: None,
+            };
+            debug!("add route {}", route);
+            nl.add_route(&route)?;
+            *self.server_route.borrow_mut() = Some(route);
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            warn!("route server {} outside the tunnel fail, {}", server, e);
+        }
+    }
+
     fn forward_remote(&self, kind: IpDataKind, pkt: &[u8]) -> Result<()> {
         let s = match self.socket() {
             Some(s) => s,
             None => return Ok(()),
         };
+        let clamped = self.clamp_mss(kind, pkt);
+        let pkt = clamped.as_deref().unwrap_or(pkt);
 
-        let msg = self.new_msg()?.ip_data()?.kind(kind)?.payload(pkt)?;
-
-        //ignore failure
-        let _ = s.send(&msg.build()?);
+        self.send_data(s, kind, pkt)?;
 
         self.state.borrow_mut().tx_bytes += pkt.len() as u64;
 
         Ok(())
     }
 
-    fn forward_local(&self, pkt: &[u8]) -> Result<()> {
-        //is valid ip packet?
-        let _ = source_ip(pkt)?;
+    // Sends an ip data message, split in fragments if it is larger than `--fragment`.
+    fn send_data(&self, s: &Socket, kind: IpDataKind, pkt: &[u8]) -> Result<()> {
+        let msg = self
+            .new_msg()?
+            .ip_data()?
+            .kind(kind)?
+            .payload(pkt)?
+            .build()?;
+        let Some(size) = self.config.fragment.filter(|v| msg.len() > *v as usize) else {
+            self.send(s, &msg);
+            return Ok(());
+        };
 
-        //ignore failure
-        let _ = write(self.tun(), pkt);
+        let body = IpDataBuilder::default().kind(kind)?.payload(pkt)?.build()?;
+        let (id, pieces) = self.fragments.borrow_mut().split(&body, size)?;
+        for (index, piece) in pieces.iter().enumerate() {
+            let msg = self
+                .new_msg()?
+                .fragment()?
+                .id(id)?
+                .index(index as u8, pieces.len() as u8)?
+                .payload(piece)?;
+            self.send(s, &msg.build()?);
+        }
+
+        Ok(())
+    }
+
+    // A copy of `pkt` with the MSS of a SYN lowered to `--mss-clamp`, if needed.
+    // The automatic one fits the path MTU as well, once discovered.
+    fn clamp_mss(&self, kind: IpDataKind, pkt: &[u8]) -> Option<Vec<u8>> {
+        let clamp = self
+            .config
+            .mss_clamp
+            .filter(|_| kind != IpDataKind::Ether)?;
+        let mtu = match self.pmtu.borrow().as_ref().and_then(|v| v.tun_mtu(false)) {
+            Some(mtu) => mtu.min(self.config.mtu),
+            None => self.config.mtu,
+        };
+        mss::clamp(pkt, clamp.mss(mtu, kind == IpDataKind::V6))
+    }
+
+    fn forward_local(&self, kind: IpDataKind, pkt: &[u8]) -> Result<()> {
+        //is packet of the tunnel mode?
+        if self.config.tap != (kind == IpDataKind::Ether) {
+            return Err(crate::Error::InvalidPacket.into());
+        }
+
+        if self.config.tap {
+            //is ethernet frame?
+            let _ = source_mac(pkt)?;
+        } else {
+            //is valid ip packet?
+            let _ = source_ip(pkt)?;
+        }
+
+        let clamped = self.clamp_mss(kind, pkt);
+        let pkt = clamped.as_deref().unwrap_or(pkt);
+        self.write_tun(pkt);
 
         self.state.borrow_mut().rx_bytes += pkt.len() as u64;
 
         Ok(())
     }
 
-    fn send_echo(&self) -> Result<()> {
+    // Sends a keepalive, or a path MTU probe padded to a datagram of `probe` bytes.
+    fn send_echo(&self, id: u32, probe: Option<u16>) -> Result<()> {
         let s = match self.socket() {
             Some(s) => s,
             None => return Ok(()),
@@ -147,7 +299,8 @@ impl Client {
         let mut msg = self
             .new_msg()?
             .echo_req()?
-            .id(self.state.borrow().gen_id())?;
+            .id(id)?
+            .ext(&EchoExt::ClientId(self.state.borrow().client_id))?;
 
         if let Some(ref addr4) = self.config.loc_tun_in {
             msg = msg.ipv4_addr(addr4.addr())?;
@@ -157,12 +310,239 @@ impl Client {
             msg = msg.ipv6_addr(addr6.addr())?;
         }
 
-        //ignore failure
-        let _ = s.send(&msg.build()?);
+        if let Some(size) = probe {
+            let msg = msg.pad(size as usize - MSG_HEADER_SIZE)?;
+            // one above the interface MTU fails at once, it is taken as lost
+            if let Err(e) = pmtu::send_probe(s, &msg.build()?) {
+                trace!("send probe of {} bytes fail. {:?}", size, e);
+            }
+            return Ok(());
+        }
+
+        self.send(s, &msg.build()?);
 
         Ok(())
     }
 
+    // Sends the path MTU probe due, once the server answers. A new path MTU is
+    // reported and, with `--pmtu-discovery adjust`, applied to the interface.
+    fn probe_pmtu(&self) -> Result<()> {
+        if self.state.borrow().last_ack.is_none() {
+            return Ok(());
+        }
+        let mut pmtu = self.pmtu.borrow_mut();
+        let Some(discovery) = pmtu.as_mut() else {
+            return Ok(());
+        };
+
+        let found = discovery.pmtu();
+        if let Some((id, size)) = discovery.next(self.state.borrow().gen_id()) {
+            self.send_echo(id, Some(size))?;
+        }
+        if discovery.pmtu() == found {
+            return Ok(());
+        }
+
+        info!("path mtu {:?}", discovery.pmtu());
+        if self.config.pmtu_discovery == PmtuPolicy::Adjust
+            && let Some(mtu) = discovery.tun_mtu(self.config.tap)
+        {
+            self.update_mtu(mtu.min(self.config.mtu));
+        }
+
+        Ok(())
+    }
+
+    // Handles a message of the server, an error drops it.
+    fn handle_msg(&self, src: SocketAddr, msg: MsgPacket<&[u8]>) -> Result<()> {
+        match msg.op() {
+            Ok(Op::EchoAck) => {
+                debug!("received echo ack");
+                self.state.borrow_mut().last_ack = Some(Instant::now());
+                self.handle_echo_ack(EchoPacket::new(msg.payload()?)?);
+            }
+            Ok(Op::IpData) => {
+                self.state.borrow_mut().last_rx = Some(Instant::now());
+                let data = IpDataPacket::new(msg.payload()?)?;
+                self.forward_local(data.kind()?, data.payload()?)?;
+            }
+            Ok(Op::Fragment) => {
+                self.state.borrow_mut().last_rx = Some(Instant::now());
+                let frag = FragmentPacket::new(msg.payload()?)?;
+                let body = self.fragments.borrow_mut().add(src, &frag);
+                match body {
+                    Ok(Some(body)) => {
+                        let data = IpDataPacket::new(&body[..])?;
+                        self.forward_local(data.kind()?, data.payload()?)?;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        debug!("drop fragment from {:}. {:?}", src, e);
+                        self.faults.add(Fault::Reassembly);
+                    }
+                }
+            }
+            Ok(Op::EchoReq) => {
+                debug!("received echo req(from old version server?)");
+                self.state.borrow_mut().last_ack = Some(Instant::now());
+            }
+            Ok(Op::Disconnect) => {
+                info!("server disconnected, reconnect...");
+                self.update_dns(Dns::default());
+                // trigger reconnect on the next keepalive
+                let mut state = self.state.borrow_mut();
+                state.last_ack = None;
+                state.last_rx = None;
+                state.last_connect = None;
+            }
+            Err(e) => {
+                debug!("unknown op from {:}. {:?}", src, e);
+                self.faults.add(Fault::UnknownOp);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn update_mtu(&self, mtu: u16) {
+        let dev = match self.rt.tun_name {
+            Some(ref dev) => dev,
+            None => return,
+        };
+
+        let result = netconf::in_netns(self.config.netns.as_deref(), || {
+            Netlink::new()?.set_mtu(dev, mtu)
+        });
+        match result {
+            Ok(()) => info!("set mtu {} dev {}", mtu, dev),
+            Err(e) => warn!("set mtu fail, {}", e),
+        }
+    }
+
+    fn handle_echo_ack<T: AsRef<[u8]>>(&self, echo: EchoPacket<T>) {
+        if let Some(discovery) = self.pmtu.borrow_mut().as_mut()
+            && let Ok(id) = echo.id()
+            && discovery.ack(id)
+        {
+            debug!("path mtu probe {:x} acked", id);
+        }
+
+        let mut dns = Dns::default();
+        let mut routes = Vec::new();
+        for ext in echo.exts() {
+            match ext {
+                EchoExt::Dns(addr) => dns.servers.push(addr),
+                EchoExt::DnsSearch(domain) => dns.search.push(domain),
+                EchoExt::Route(net) => routes.push(net),
+                EchoExt::ClientId(_) => {}
+            }
+        }
+        self.update_dns(dns);
+        // also removes the routes installed before `accept-routes` was turned off
+        if !self.config.accept_routes {
+            routes.clear();
+        }
+        self.update_pushed_routes(&routes);
+    }
+
+    // Whether a route to `net` on the interface would take the datagrams to the
+    // server into the tunnel, unless they are kept outside of it already.
+    fn covers_server(&self, net: &IpNet) -> bool {
+        if self.config.netns.is_some()
+            || self.config.fwmark.is_some()
+            || self.server_route.borrow().is_some()
+        {
+            return false;
+        }
+
+        self.socket()
+            .and_then(|s| s.peer_addr().ok())
+            .is_some_and(|v| net.contains(&v.ip()))
+    }
+
+    // Installs the routes pushed by the server and removes the ones no longer pushed,
+    // routes attached by the local config are left alone.
+    fn update_pushed_routes(&self, nets: &[IpNet]) {
+        let dev = match self.rt.tun_name {
+            Some(ref dev) => dev,
+            None => return,
+        };
+
+        let routes = nets
+            .iter()
+            .filter(|net| !self.config.routes.iter().any(|(n, ..)| n == *net))
+            .filter(|net| {
+                let covers = self.covers_server(net);
+                if covers {
+                    debug!("pushed route {} covers the server, ignored", net);
+                }
+                !covers
+            })
+            .map(|net| Route::new(*net, dev, &self.config.table, &self.config.metric))
+            .collect::<std::result::Result<Vec<_>, _>>();
+        let routes = match routes {
+            Ok(routes) => routes,
+            Err(e) => {
+                warn!("pushed routes ignored, {}", e);
+                return;
+            }
+        };
+
+        if *self.pushed_routes.borrow() == routes {
+            return;
+        }
+
+        let result = netconf::in_netns(self.config.netns.as_deref(), || {
+            let nl = Netlink::new()?;
+            let mut pushed = self.pushed_routes.borrow_mut();
+            pushed.retain(|route| {
+                if routes.contains(route) {
+                    return true;
+                }
+                info!("remove pushed route {}", route);
+                let _ = nl.del_route(route).inspect_err(|e| warn!("{}", e));
+                false
+            });
+
+            for route in routes {
+                if !pushed.contains(&route) {
+                    info!("add pushed route {}", route);
+                    match nl.add_route(&route) {
+                        Ok(()) => pushed.push(route),
+                        Err(e) => warn!("{}", e),
+                    }
+                }
+            }
+            Ok::<_, crate::Error>(())
+        });
+
+        if let Err(e) = result {
+            warn!("update pushed routes fail, {}", e);
+        }
+    }
+
+    // Applies the DNS pushed by the server, restores the original one if none is pushed.
+    fn update_dns(&self, dns: Dns) {
+        let resolver = match self.rt.resolver {
+            Some(ref resolver) => resolver,
+            None => return,
+        };
+
+        if *self.dns.borrow() == dns {
+            return;
+        }
+
+        let result = if dns.is_empty() {
+            resolver.restore()
+        } else {
+            resolver.apply(&dns)
+        };
+        match result {
+            Ok(()) => *self.dns.borrow_mut() = dns,
+            Err(e) => warn!("update dns fail, {}", e),
+        }
+    }
+
     fn new_msg(&self) -> Result<MsgBuilder<'_>> {
         let builder = MsgBuilder::default()
             .with_cryptor(self.config.cryptor())?
@@ -195,6 +575,21 @@ impl Client {
 
         Ok(())
     }
+
+    fn reload_config(&mut self) -> Result<Diff> {
+        let (config, diff) = self.config.reload(self.rt.load_config()?);
+        self.rt.update_routes(&self.config, &config)?;
+        self.config = Rc::new(config);
+
+        if diff.is_changed("remote") {
+            *self.server_index.borrow_mut() = 0;
+            let server = self.get_current_server_addr();
+            info!("server list changed, switching to {}", server);
+            self.change_server(server)?;
+        }
+
+        Ok(diff)
+    }
 }
 
 impl std::fmt::Display for Client {
@@ -220,6 +615,16 @@ impl std::fmt::Display for Client {
                 .map(|v| v.to_string())
                 .unwrap_or_else(|_| "NA".to_string())
         )?;
+        if let Some(ref discovery) = *self.pmtu.borrow() {
+            writeln!(
+                f,
+                "{:<15} {}",
+                "pmtu:",
+                discovery
+                    .pmtu()
+                    .map_or_else(|| "probing".to_string(), |v| v.to_string())
+            )?;
+        }
         if let Some(ipv4) = self.config.loc_tun_in {
             writeln!(f, "{:<15} {}", "ipv4:", ipv4)?;
         }
@@ -261,18 +666,39 @@ impl std::fmt::Display for Client {
         )?;
         writeln!(f, "{:<15} {}", "rx:", Size::from_bytes(state.rx_bytes))?;
         writeln!(f, "{:<15} {}", "tx:", Size::from_bytes(state.tx_bytes))?;
+        write!(f, "{:}", self.faults)?;
         Ok(())
     }
 }
 
 impl poll::Reactor for Client {
+    fn tun_fd(&self) -> RawFd {
+        self.tun().as_raw_fd()
+    }
+
     fn socket_fd(&self) -> Option<RawFd> {
         self.socket().map(|s| s.as_raw_fd())
     }
 
+    fn start(&mut self) -> Result<()> {
+        let current_server = self.get_current_server_addr();
+        self.connect(current_server.as_str());
+        Ok(())
+    }
+
     fn tunnel_recv(&self) -> Result<()> {
-        let mut buf = unsafe { MaybeUninit::assume_init(MaybeUninit::<[u8; 1500]>::uninit()) };
+        let mut buf =
+            unsafe { MaybeUninit::assume_init(MaybeUninit::<[u8; poll::BUFFER_SIZE]>::uninit()) };
         let size = read(self.tun().as_raw_fd(), &mut buf)?;
+        // a packet filling the buffer may be truncated
+        if size == buf.len() {
+            self.faults.add(Fault::Oversized);
+            return Ok(());
+        }
+        if self.config.tap {
+            return self.forward_remote(IpDataKind::Ether, &buf[..size]);
+        }
+
         match buf[0] >> 4 {
             4 => self.forward_remote(IpDataKind::V4, &buf[..size])?,
             6 => self.forward_remote(IpDataKind::V6, &buf[..size])?,
@@ -288,32 +714,31 @@ impl poll::Reactor for Client {
             None => return Ok(()),
         };
 
-        let mut buf = unsafe { MaybeUninit::assume_init(MaybeUninit::<[u8; 1500]>::uninit()) };
+        let mut buf =
+            unsafe { MaybeUninit::assume_init(MaybeUninit::<[u8; poll::BUFFER_SIZE]>::uninit()) };
         match s.recv_from(&mut buf) {
             Ok((size, src)) => {
                 trace!("receive from  {:}, size {:}", src, size);
+                if size == buf.len() {
+                    debug!("oversized packet from {:}", src);
+                    self.faults.add(Fault::Oversized);
+                    return Ok(());
+                }
                 match MsgPacket::<&[u8]>::with_cryptor(&mut buf[..size], self.config.cryptor()) {
                     Ok(msg) => {
-                        match msg.op() {
-                            Ok(Op::EchoAck) => {
-                                debug!("received echo ack");
-                                self.state.borrow_mut().last_ack = Some(Instant::now());
-                            }
-                            Ok(Op::IpData) => {
-                                self.state.borrow_mut().last_rx = Some(Instant::now());
-                                self.forward_local(IpDataPacket::new(msg.payload()?)?.payload()?)?;
-                            }
-                            Ok(Op::EchoReq) => {
-                                debug!("received echo req(from old version server?)");
-                                self.state.borrow_mut().last_ack = Some(Instant::now());
-                            }
-                            _ => debug!("unexpected msg {:?}", msg.op()),
-                        };
+                        if let Err(e) = self.handle_msg(src, msg) {
+                            debug!("invalid msg from {:}. {:?}", src, e);
+                            self.faults.add(Fault::Invalid);
+                            return Ok(());
+                        }
 
                         // Reset connection attempts on successful connection
                         self.state.borrow_mut().connect_attempts = 0;
                     }
-                    _ => trace!("invalid packet"),
+                    Err(e) => {
+                        trace!("invalid packet. {:?}", e);
+                        self.faults.add(Fault::of(&e));
+                    }
                 }
             }
             Err(e) => {
@@ -382,48 +807,81 @@ impl poll::Reactor for Client {
             self.connect(next_server.as_str());
         }
 
+        for _ in 0..self.fragments.get_mut().expire() {
+            self.faults.add(Fault::Reassembly);
+        }
+
         if check_timeout(last_echo, &keepalive_interval) {
             self.state.borrow_mut().last_echo = Some(Instant::now());
-            self.send_echo()?;
+            self.send_echo(self.state.borrow().gen_id(), None)?;
         }
 
+        self.probe_pmtu()?;
+
         Ok(())
     }
 
-    fn handle_control_connection(&mut self, fd: RawFd) -> Result<()> {
-        let mut us = unsafe { UnixStream::from_raw_fd(fd) };
-        let mut buf = [0u8; 64];
-
-        // First try to read from the socket in case it's a command
-        if let Ok(n) = us.read(&mut buf) {
-            let resp = if let Ok(s) = std::str::from_utf8(&buf[..n]) {
-                if s.trim() == "change-server" {
-                    let next_server = self.get_next_server_addr();
-                    info!(
-                        "Received change-server command, switching to {}",
-                        next_server
-                    );
-                    match self.change_server(next_server.clone()) {
-                        Ok(()) => {
-                            format!("Changed server to {}\n", next_server)
-                        }
-                        Err(e) => {
-                            warn!("Failed to change server: {}", e);
-                            format!("Failed to change server: {}\n", e)
-                        }
+    fn handle_control(&mut self, cmd: &str) -> String {
+        match cmd {
+            "change-server" => {
+                let next_server = self.get_next_server_addr();
+                info!(
+                    "Received change-server command, switching to {}",
+                    next_server
+                );
+                match self.change_server(next_server.clone()) {
+                    Ok(()) => {
+                        format!("Changed server to {}\n", next_server)
+                    }
+                    Err(e) => {
+                        warn!("Failed to change server: {}", e);
+                        format!("Failed to change server: {}\n", e)
                     }
-                } else if s.trim() == "show-info" {
-                    self.to_string()
-                } else {
-                    format!("Unknown command: {}\n", s.trim())
                 }
-            } else {
-                "Invalid UTF-8 sequence\n".to_string()
-            };
-
-            let _ = us.write(resp.as_bytes());
+            }
+            "show-info" => self.to_string(),
+            "reload" => match self.reload_config() {
+                Ok(diff) => diff.to_string(),
+                Err(e) => {
+                    warn!("Failed to reload config: {}", e);
+                    format!("Failed to reload config: {}\n", e)
+                }
+            },
+            _ => format!("Unknown command: {}\n", cmd),
         }
+    }
 
+    fn shutdown(&mut self) -> Result<()> {
+        info!("shutting down...");
+        if let Some(s) = self.socket() {
+            self.send(s, &self.new_msg()?.disconnect()?.build()?);
+        }
+        // also restores a resolv.conf left by a run that died
+        if let Some(ref resolver) = self.rt.resolver {
+            let _ = resolver
+                .restore()
+                .inspect_err(|e| warn!("restore dns fail, {}", e));
+        }
+        self.update_pushed_routes(&[]);
+        if let Some(route) = self.server_route.borrow_mut().take() {
+            let _ = Netlink::new()
+                .and_then(|nl| nl.del_route(&route))
+                .inspect_err(|e| warn!("{}", e));
+        }
+        self.rt.deconfig_tun(&self.config)?;
         Ok(())
     }
+
+    fn reload(&mut self) -> Result<()> {
+        match self.reload_config() {
+            Ok(diff) => {
+                info!("config reloaded, {}", diff.to_string().trim_end());
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Failed to reload config: {}", e);
+                Err(e)
+            }
+        }
+    }
 }
@@ -1,11 +1,20 @@
+use crate::acl;
 #[cfg(feature = "holepunch")]
 use crate::config::rndz;
 use crate::cryptor;
+use crate::error::Error;
+use crate::fragment;
+use crate::netconf::{table_id, Route, Rule, RT_TABLE_MAIN};
+use crate::shaper::Limit;
 use crate::util::build_server_addr;
 
 use ipnet::IpNet;
 use ipnet::{Ipv4Net, Ipv6Net};
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::Duration;
 
 const DEFAULT_MTU: u16 = 1300;
@@ -13,18 +22,215 @@ const DEFAULT_RECONNECT_TIMEOUT: Duration = Duration::from_secs(60 * 10);
 const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(7);
 const DEFAULT_CLIENT_TIMEOUT: Duration = Duration::from_secs(120);
 const DEFAULT_REBIND_TIMEOUT: Duration = Duration::from_secs(60 * 30);
+pub const DEFAULT_CIPHER: &str = "aes-128";
+
+/// Parses a route in the form of "network/prefix[=gw][@metric]".
+pub fn parse_route(s: &str) -> Result<(IpNet, Option<IpAddr>, Option<u32>), Error> {
+    let (s, metric) = match s.split_once('@') {
+        Some((s, metric)) => (
+            s,
+            Some(
+                metric
+                    .parse()
+                    .map_err(|_| Error::InvalidArg("invalid metric".into()))?,
+            ),
+        ),
+        None => (s, None),
+    };
+    let mut parts = s.splitn(2, '=');
+    let net: IpNet = parts
+        .next()
+        .ok_or(Error::InvalidArg("Invalid route network".into()))?
+        .parse()
+        .map_err(|_| Error::InvalidArg("invalid route".into()))?;
+    let gw: Option<IpAddr> = parts
+        .next()
+        .map(|gw| {
+            gw.parse()
+                .map_err(|_| Error::InvalidArg("invalid gateway".into()))
+        })
+        .transpose()?;
+
+    Ok((net, gw, metric))
+}
+
+/// How a server handles traffic from one client to another.
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientPolicy {
+    /// Written to the tun interface and forwarded by the kernel.
+    #[default]
+    Kernel,
+    /// Sent to the other client directly.
+    Hairpin,
+    /// Dropped.
+    Isolate,
+}
+
+impl FromStr for ClientPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kernel" => Ok(Self::Kernel),
+            "hairpin" => Ok(Self::Hairpin),
+            "isolate" => Ok(Self::Isolate),
+            _ => Err(Error::InvalidArg(format!("invalid client policy {:?}", s))),
+        }
+    }
+}
+
+/// Who may take over a virtual address already at another real address.
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VipPolicy {
+    /// Anyone, the last sender wins.
+    #[default]
+    Roam,
+    /// The same client from a new address only, until the address times out or disconnects.
+    Bind,
+}
+
+impl FromStr for VipPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "roam" => Ok(Self::Roam),
+            "bind" => Ok(Self::Bind),
+            _ => Err(Error::InvalidArg(format!("invalid vip policy {:?}", s))),
+        }
+    }
+}
+
+/// What a server does with a new client or vip over an admission limit.
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AdmissionPolicy {
+    /// Drops the packets of the new one.
+    #[default]
+    Reject,
+    /// Evicts the oldest idle one to make room.
+    Evict,
+}
+
+impl FromStr for AdmissionPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "evict" => Ok(Self::Evict),
+            _ => Err(Error::InvalidArg(format!(
+                "invalid admission policy {:?}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Whether a client discovers the path MTU to its server.
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PmtuPolicy {
+    #[default]
+    Off,
+    /// Probes the path and reports the path MTU.
+    Probe,
+    /// Also fits the interface MTU to the path, never above `--mtu`.
+    Adjust,
+}
+
+impl FromStr for PmtuPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "probe" => Ok(Self::Probe),
+            "adjust" => Ok(Self::Adjust),
+            _ => Err(Error::InvalidArg(format!("invalid pmtu policy {:?}", s))),
+        }
+    }
+}
+
+/// MSS the TCP SYN segments through the tunnel are clamped to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MssClamp {
+    /// Fits a full sized packet of the interface.
+    Auto,
+    Mss(u16),
+}
+
+impl MssClamp {
+    /// The MSS of a segment to or from an interface of `mtu` bytes.
+    pub fn mss(&self, mtu: u16, ipv6: bool) -> u16 {
+        match self {
+            // IP and TCP headers
+            Self::Auto => mtu.saturating_sub(if ipv6 { 40 + 20 } else { 20 + 20 }),
+            Self::Mss(mss) => *mss,
+        }
+    }
+}
+
+impl FromStr for MssClamp {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            s => s
+                .parse()
+                .ok()
+                .filter(|v| *v > 0)
+                .map(Self::Mss)
+                .ok_or_else(|| Error::InvalidArg(format!("invalid mss clamp {:?}", s))),
+        }
+    }
+}
+
+/// Parses a client group in the form of "network/prefix=policy".
+pub fn parse_client_group(s: &str) -> Result<(IpNet, ClientPolicy), Error> {
+    let (net, policy) = s
+        .split_once('=')
+        .ok_or_else(|| Error::InvalidArg(format!("invalid client group {:?}", s)))?;
+    let net = net
+        .parse()
+        .map_err(|_| Error::InvalidArg(format!("invalid client group {:?}", s)))?;
+
+    Ok((net, policy.parse()?))
+}
+
+/// Parses the rate limits of a client group in the form of
+/// "network/prefix=ingress[,egress]", a limit is `rate[:burst]` or `none`.
+pub fn parse_client_limit(s: &str) -> Result<(IpNet, Option<Limit>, Option<Limit>), Error> {
+    let invalid = || Error::InvalidArg(format!("invalid client limit {:?}", s));
+    let (net, limits) = s.split_once('=').ok_or_else(invalid)?;
+    let net = net.parse().map_err(|_| invalid())?;
+    let (ingress, egress) = limits.split_once(',').unwrap_or((limits, limits));
+    let limit = |v: &str| match v {
+        "none" => Ok(None),
+        v => v.parse().map(Some),
+    };
+
+    Ok((net, limit(ingress)?, limit(egress)?))
+}
 
 #[derive(Default)]
 pub struct Config {
     pub ifname: Option<String>,
     pub mtu: u16,
+    // largest datagram, an ip data message above it is split in fragments
+    pub fragment: Option<u16>,
+    pub pmtu_discovery: PmtuPolicy,
+    pub mss_clamp: Option<MssClamp>,
     pub loc_tun_in: Option<Ipv4Net>,
     pub loc_tun_in6: Option<Ipv6Net>,
     pub listen_addr: Option<SocketAddr>,
     pub server_addrs: Option<Vec<String>>,
     pub cryptor: Option<Box<dyn cryptor::Cryptor>>,
     pub daemonize: bool,
-    pub routes: Vec<(IpNet, Option<IpAddr>)>,
+    pub routes: Vec<(IpNet, Option<IpAddr>, Option<u32>)>,
     pub keepalive_interval: Duration,
     pub reconnect_timeout: Duration,
     pub rebind_timeout: Duration,
@@ -32,13 +238,91 @@ pub struct Config {
     pub table: Option<String>,
     pub metric: Option<String>,
     pub fwmark: Option<u32>,
+    pub default_route: bool,
+    pub tap: bool,
+    pub netns: Option<String>,
+    pub masquerade: Option<String>,
+    pub up: Option<String>,
+    pub down: Option<String>,
+    pub client_connect: Option<String>,
+    pub client_disconnect: Option<String>,
+    pub push_route: Vec<IpNet>,
+    pub push_dns: Vec<IpAddr>,
+    pub push_domain: Vec<String>,
+    pub accept_dns: bool,
+    pub accept_routes: bool,
+    pub multicast: Vec<IpNet>,
+    pub broadcast: bool,
+    pub client_to_client: ClientPolicy,
+    pub client_groups: Vec<(IpNet, ClientPolicy)>,
+    pub acl: Vec<acl::Rule>,
+    pub acl_default: acl::Action,
+    pub strict_source: bool,
+    pub vip_policy: VipPolicy,
+    pub ingress_limit: Option<Limit>,
+    pub egress_limit: Option<Limit>,
+    pub client_limits: Vec<(IpNet, Option<Limit>, Option<Limit>)>,
+    pub max_clients: Option<usize>,
+    pub max_clients_per_ip: Option<usize>,
+    pub max_vips: Option<usize>,
+    pub admission: AdmissionPolicy,
+    pub state_file: Option<PathBuf>,
     pub wait_dns: bool,
     pub rebind: bool,
     #[cfg(feature = "holepunch")]
     pub rndz: Option<rndz::Config>,
     pub info: bool,
     pub change_server: bool,
+    pub reload: bool,
     pub pre_resolve_dns: bool,
+    pub config_file: Option<PathBuf>,
+    pub tunnel: Option<String>,
+}
+
+/// Changes between the running config and a reloaded one.
+#[derive(Default, Debug)]
+pub struct Diff {
+    pub changed: Vec<&'static str>,
+    pub restart_required: Vec<&'static str>,
+}
+
+impl Diff {
+    pub fn is_changed(&self, name: &str) -> bool {
+        self.changed.contains(&name)
+    }
+}
+
+impl Display for Diff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.changed.is_empty() && self.restart_required.is_empty() {
+            return writeln!(f, "no change");
+        }
+        if !self.changed.is_empty() {
+            writeln!(f, "{:<17} {}", "changed:", self.changed.join(", "))?;
+        }
+        if !self.restart_required.is_empty() {
+            writeln!(
+                f,
+                "{:<17} {}",
+                "restart required:",
+                self.restart_required.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn check<T: PartialEq>(name: &'static str, old: &T, new: &T, diff: &mut Diff) {
+    if old != new {
+        diff.changed.push(name);
+    }
+}
+
+fn keep<T: PartialEq + Clone>(name: &'static str, old: &T, new: &mut T, diff: &mut Diff) {
+    if old != new {
+        diff.restart_required.push(name);
+        *new = old.clone();
+    }
 }
 
 impl Config {
@@ -71,6 +355,334 @@ impl Config {
         self
     }
 
+    /// Merges a reloaded config. Settings which can't be changed at runtime are kept
+    /// from the running config and reported as restart required.
+    pub fn reload(&self, mut new: Config) -> (Config, Diff) {
+        let mut diff = Diff::default();
+
+        if self.is_client() == new.is_client() {
+            check("remote", &self.server_addrs, &new.server_addrs, &mut diff);
+        } else {
+            keep(
+                "remote",
+                &self.server_addrs,
+                &mut new.server_addrs,
+                &mut diff,
+            );
+        }
+        check("route", &self.routes, &new.routes, &mut diff);
+        check(
+            "keepalive",
+            &self.keepalive_interval,
+            &new.keepalive_interval,
+            &mut diff,
+        );
+        check(
+            "reconnect-timeo",
+            &self.reconnect_timeout,
+            &new.reconnect_timeout,
+            &mut diff,
+        );
+        check(
+            "rebind-timeo",
+            &self.rebind_timeout,
+            &new.rebind_timeout,
+            &mut diff,
+        );
+        check(
+            "client-timeo",
+            &self.client_timeout,
+            &new.client_timeout,
+            &mut diff,
+        );
+        check("fragment", &self.fragment, &new.fragment, &mut diff);
+        check("mss-clamp", &self.mss_clamp, &new.mss_clamp, &mut diff);
+        check("rebind", &self.rebind, &new.rebind, &mut diff);
+        check("down", &self.down, &new.down, &mut diff);
+        check("push-route", &self.push_route, &new.push_route, &mut diff);
+        check(
+            "accept-routes",
+            &self.accept_routes,
+            &new.accept_routes,
+            &mut diff,
+        );
+        check("push-dns", &self.push_dns, &new.push_dns, &mut diff);
+        check(
+            "push-domain",
+            &self.push_domain,
+            &new.push_domain,
+            &mut diff,
+        );
+        check("multicast", &self.multicast, &new.multicast, &mut diff);
+        check("broadcast", &self.broadcast, &new.broadcast, &mut diff);
+        check(
+            "client-to-client",
+            &self.client_to_client,
+            &new.client_to_client,
+            &mut diff,
+        );
+        check(
+            "client-group",
+            &self.client_groups,
+            &new.client_groups,
+            &mut diff,
+        );
+        check("acl", &self.acl, &new.acl, &mut diff);
+        check(
+            "acl-default",
+            &self.acl_default,
+            &new.acl_default,
+            &mut diff,
+        );
+        check(
+            "strict-source",
+            &self.strict_source,
+            &new.strict_source,
+            &mut diff,
+        );
+        check("vip-policy", &self.vip_policy, &new.vip_policy, &mut diff);
+        check(
+            "ingress-limit",
+            &self.ingress_limit,
+            &new.ingress_limit,
+            &mut diff,
+        );
+        check(
+            "egress-limit",
+            &self.egress_limit,
+            &new.egress_limit,
+            &mut diff,
+        );
+        check(
+            "client-limit",
+            &self.client_limits,
+            &new.client_limits,
+            &mut diff,
+        );
+        check(
+            "max-clients",
+            &self.max_clients,
+            &new.max_clients,
+            &mut diff,
+        );
+        check(
+            "max-clients-per-ip",
+            &self.max_clients_per_ip,
+            &new.max_clients_per_ip,
+            &mut diff,
+        );
+        check("max-vips", &self.max_vips, &new.max_vips, &mut diff);
+        check("admission", &self.admission, &new.admission, &mut diff);
+        check(
+            "client-connect",
+            &self.client_connect,
+            &new.client_connect,
+            &mut diff,
+        );
+        check(
+            "client-disconnect",
+            &self.client_disconnect,
+            &new.client_disconnect,
+            &mut diff,
+        );
+        if self.cryptor().map(|c| c.auth_key()) != new.cryptor().map(|c| c.auth_key()) {
+            diff.changed.push("key");
+        }
+
+        keep("ifname", &self.ifname, &mut new.ifname, &mut diff);
+        keep("mtu", &self.mtu, &mut new.mtu, &mut diff);
+        keep(
+            "pmtu-discovery",
+            &self.pmtu_discovery,
+            &mut new.pmtu_discovery,
+            &mut diff,
+        );
+        keep(
+            "ipv4-addr",
+            &self.loc_tun_in,
+            &mut new.loc_tun_in,
+            &mut diff,
+        );
+        keep(
+            "ipv6-addr",
+            &self.loc_tun_in6,
+            &mut new.loc_tun_in6,
+            &mut diff,
+        );
+        keep("local", &self.listen_addr, &mut new.listen_addr, &mut diff);
+        keep("daemon", &self.daemonize, &mut new.daemonize, &mut diff);
+        keep("table", &self.table, &mut new.table, &mut diff);
+        keep("metric", &self.metric, &mut new.metric, &mut diff);
+        keep("fwmark", &self.fwmark, &mut new.fwmark, &mut diff);
+        keep("netns", &self.netns, &mut new.netns, &mut diff);
+        keep(
+            "masquerade",
+            &self.masquerade,
+            &mut new.masquerade,
+            &mut diff,
+        );
+        keep(
+            "state-file",
+            &self.state_file,
+            &mut new.state_file,
+            &mut diff,
+        );
+        keep("up", &self.up, &mut new.up, &mut diff);
+        keep(
+            "accept-dns",
+            &self.accept_dns,
+            &mut new.accept_dns,
+            &mut diff,
+        );
+        keep(
+            "default-route",
+            &self.default_route,
+            &mut new.default_route,
+            &mut diff,
+        );
+        keep("tap", &self.tap, &mut new.tap, &mut diff);
+        #[cfg(feature = "holepunch")]
+        keep("rndz", &self.rndz, &mut new.rndz, &mut diff);
+
+        (new, diff)
+    }
+
+    /// Routes to install on the tun interface `dev`.
+    ///
+    /// A client routes via the gateway of `--route` and, in full tunnel mode, adds default
+    /// routes. They go to the fwmark table if `--fwmark` is set, otherwise they are split
+    /// in two halves to take precedence over the existing default route.
+    pub fn tun_routes(&self, dev: &str) -> Result<Vec<Route>, Error> {
+        let mut routes = Vec::new();
+        for (net, gw, metric) in &self.routes {
+            let mut route = Route::new(*net, dev, &self.table, &self.metric)?;
+            if self.is_client() {
+                route.gateway = *gw;
+            }
+            if metric.is_some() {
+                route.metric = *metric;
+            }
+            routes.push(route);
+        }
+
+        if !self.is_full_tunnel() {
+            return Ok(routes);
+        }
+
+        let nets: &[&str] = match (self.fwmark.is_some(), self.loc_tun_in6.is_some()) {
+            (true, false) => &["0.0.0.0/0"],
+            (true, true) => &["0.0.0.0/0", "::/0"],
+            (false, false) => &["0.0.0.0/1", "128.0.0.0/1"],
+            (false, true) => &["0.0.0.0/1", "128.0.0.0/1", "::/1", "8000::/1"],
+        };
+        for net in nets {
+            let mut route = Route::new(net.parse().unwrap(), dev, &None, &self.metric)?;
+            if self.fwmark.is_some() {
+                route.table = Some(self.full_tunnel_table()?);
+            }
+            routes.push(route);
+        }
+
+        Ok(routes)
+    }
+
+    /// Policy rules of the full tunnel mode, the same as wg-quick does. Traffic without the
+    /// fwmark of the tunnel socket goes to the table of the default routes, and routes more
+    /// specific than a default route of the main table are still honored.
+    pub fn tun_rules(&self) -> Result<Vec<Rule>, Error> {
+        let fwmark = match self.fwmark {
+            Some(fwmark) if self.is_full_tunnel() => fwmark,
+            _ => return Ok(Vec::new()),
+        };
+
+        let table = self.full_tunnel_table()?;
+        let families: &[bool] = match self.loc_tun_in6 {
+            Some(_) => &[false, true],
+            None => &[false],
+        };
+        Ok(families
+            .iter()
+            .flat_map(|&ipv6| {
+                [
+                    Rule {
+                        ipv6,
+                        priority: None,
+                        not: true,
+                        fwmark: Some(fwmark),
+                        table,
+                        suppress_prefixlen: None,
+                    },
+                    Rule {
+                        ipv6,
+                        priority: None,
+                        not: false,
+                        fwmark: None,
+                        table: RT_TABLE_MAIN,
+                        suppress_prefixlen: Some(0),
+                    },
+                ]
+            })
+            .collect())
+    }
+
+    /// Checks that `--fragment` splits a full sized packet in at most 255 pieces.
+    pub fn check_fragment(&self) -> Result<(), Error> {
+        match self.fragment {
+            Some(size) => fragment::check_size(size, self.mtu, self.tap),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether all traffic is routed through the tunnel.
+    pub fn is_full_tunnel(&self) -> bool {
+        self.default_route && self.is_client()
+    }
+
+    /// Whether packets to `dst` are replicated to all clients, only the allowed
+    /// multicast groups and broadcast of the tunnel subnet if enabled are.
+    pub fn is_flooded(&self, dst: &IpAddr) -> bool {
+        let broadcast = match dst {
+            IpAddr::V4(dst) => {
+                dst.is_broadcast() || self.loc_tun_in.is_some_and(|v| v.broadcast() == *dst)
+            }
+            IpAddr::V6(_) => false,
+        };
+
+        (broadcast && self.broadcast)
+            || (dst.is_multicast() && self.multicast.iter().any(|v| v.contains(dst)))
+    }
+
+    /// Policy of traffic from the client `vip` to other clients, set by the longest
+    /// matching `--client-group` or `--client-to-client`.
+    pub fn client_policy(&self, vip: &IpAddr) -> ClientPolicy {
+        self.client_groups
+            .iter()
+            .filter(|(net, _)| net.contains(vip))
+            .max_by_key(|(net, _)| net.prefix_len())
+            .map_or(self.client_to_client, |(_, policy)| *policy)
+    }
+
+    /// Ingress and egress rate limits of the client `vip`, set by the longest
+    /// matching `--client-limit` or `--ingress-limit` and `--egress-limit`.
+    pub fn client_limits(&self, vip: &IpAddr) -> (Option<Limit>, Option<Limit>) {
+        self.client_limits
+            .iter()
+            .filter(|(net, ..)| net.contains(vip))
+            .max_by_key(|(net, ..)| net.prefix_len())
+            .map_or(
+                (self.ingress_limit, self.egress_limit),
+                |(_, ingress, egress)| (*ingress, *egress),
+            )
+    }
+
+    // The table of the default routes with policy routing, named by `--table` or the fwmark.
+    fn full_tunnel_table(&self) -> Result<u32, Error> {
+        match self.table {
+            Some(ref table) => table_id(table),
+            None => Ok(self.fwmark.unwrap_or_default()),
+        }
+    }
+
     #[cfg(feature = "holepunch")]
     pub fn rndz(&self) -> Option<&rndz::Config> {
         self.rndz.as_ref()
@@ -123,4 +735,106 @@ mod tests {
     fn test() {
         Config::default();
     }
+
+    #[test]
+    fn test_reload() {
+        let mut running = Config::new();
+        running.with_server_addr("1.2.3.4:1234".into());
+
+        let mut new = Config::new();
+        new.with_server_addr("1.2.3.5:1234".into());
+        new.mtu = 1400;
+        new.keepalive_interval = Duration::from_secs(1);
+
+        let (config, diff) = running.reload(new);
+        assert_eq!(diff.changed, ["remote", "keepalive"]);
+        assert_eq!(diff.restart_required, ["mtu"]);
+        assert_eq!(config.mtu, running.mtu);
+
+        let (_, diff) = running.reload(Config::new());
+        assert_eq!(diff.restart_required, ["remote"]);
+    }
+
+    #[test]
+    fn test_full_tunnel() {
+        let mut config = Config::new();
+        config.with_server_addr("1.2.3.4:1234".into());
+        config.routes = vec![parse_route("10.1.0.0/16=10.0.0.1").unwrap()];
+        config.default_route = true;
+
+        let routes = config.tun_routes("mv0").unwrap();
+        assert_eq!(routes[0].gateway, Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(routes[1].to_string(), "0.0.0.0/1 dev mv0");
+        assert_eq!(routes.len(), 3);
+        assert!(config.tun_rules().unwrap().is_empty());
+
+        config.fwmark = Some(51820);
+        let routes = config.tun_routes("mv0").unwrap();
+        assert_eq!(routes[1].to_string(), "0.0.0.0/0 dev mv0 table 51820");
+        let rules = config.tun_rules().unwrap();
+        assert_eq!(rules[0].to_string(), "-4 not fwmark 0xca6c table 51820");
+        assert_eq!(rules[1].to_string(), "-4 table 254 suppress_prefixlength 0");
+    }
+
+    #[test]
+    fn test_flooded() {
+        let mut config = Config::new();
+        config.with_ip_addr("10.0.0.1/24".parse().unwrap());
+        config.multicast = vec![
+            "224.0.0.251/32".parse().unwrap(),
+            "ff02::fb/128".parse().unwrap(),
+        ];
+
+        assert!(config.is_flooded(&"224.0.0.251".parse().unwrap()));
+        assert!(config.is_flooded(&"ff02::fb".parse().unwrap()));
+        assert!(!config.is_flooded(&"239.255.255.250".parse().unwrap()));
+        assert!(!config.is_flooded(&"10.0.0.255".parse().unwrap()));
+
+        config.broadcast = true;
+        assert!(config.is_flooded(&"10.0.0.255".parse().unwrap()));
+        assert!(config.is_flooded(&"255.255.255.255".parse().unwrap()));
+        assert!(!config.is_flooded(&"10.0.0.2".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_client_policy() {
+        let mut config = Config::new();
+        config.client_to_client = ClientPolicy::Hairpin;
+        config.client_groups = vec![
+            parse_client_group("10.0.0.0/24=isolate").unwrap(),
+            parse_client_group("10.0.0.128/25=kernel").unwrap(),
+        ];
+        assert!(parse_client_group("10.0.0.0/24").is_err());
+        assert!(parse_client_group("10.0.0.0/24=none").is_err());
+
+        assert_eq!(
+            config.client_policy(&"10.0.0.2".parse().unwrap()),
+            ClientPolicy::Isolate
+        );
+        assert_eq!(
+            config.client_policy(&"10.0.0.200".parse().unwrap()),
+            ClientPolicy::Kernel
+        );
+        assert_eq!(
+            config.client_policy(&"10.1.0.2".parse().unwrap()),
+            ClientPolicy::Hairpin
+        );
+    }
+
+    #[test]
+    fn test_client_limits() {
+        let mut config = Config::new();
+        config.ingress_limit = Some("1m".parse().unwrap());
+        config.client_limits = vec![
+            parse_client_limit("10.0.0.0/24=10m,20m").unwrap(),
+            parse_client_limit("10.0.0.2/32=none").unwrap(),
+        ];
+        assert!(parse_client_limit("10.0.0.0/24").is_err());
+        assert!(parse_client_limit("10.0.0.0/24=fast").is_err());
+
+        let limits = |vip: &str| config.client_limits(&vip.parse().unwrap());
+        assert_eq!(limits("10.0.0.2"), (None, None));
+        assert_eq!(limits("10.0.0.3").1.unwrap().rate, 20_000_000);
+        assert_eq!(limits("10.1.0.2"), (config.ingress_limit, None));
+    }
 }
@@ -0,0 +1,516 @@
+#[cfg(feature = "holepunch")]
+use super::rndz;
+use super::{
+    parse_client_group, parse_client_limit, parse_route, AdmissionPolicy, ClientPolicy, Config,
+    MssClamp, PmtuPolicy, VipPolicy, DEFAULT_CIPHER,
+};
+use crate::acl;
+use crate::cryptor;
+use crate::error::Error;
+use crate::shaper::Limit;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A route entry in the form of "network/prefix[=gw][@metric]", same as `--route`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Route(pub IpNet, pub Option<IpAddr>, pub Option<u32>);
+
+impl TryFrom<String> for Route {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let (net, gw, metric) = parse_route(&s).map_err(|_| format!("invalid route {:?}", s))?;
+        Ok(Route(net, gw, metric))
+    }
+}
+
+/// A client group in the form of "network/prefix=policy", same as `--client-group`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String")]
+pub struct ClientGroup(pub IpNet, pub ClientPolicy);
+
+impl TryFrom<String> for ClientGroup {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let (net, policy) =
+            parse_client_group(&s).map_err(|_| format!("invalid client group {:?}", s))?;
+        Ok(ClientGroup(net, policy))
+    }
+}
+
+/// A rate limit in the form of "rate[:burst]", same as `--ingress-limit`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String")]
+pub struct RateLimit(pub Limit);
+
+impl TryFrom<String> for RateLimit {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let limit = s
+            .parse()
+            .map_err(|_| format!("invalid rate limit {:?}", s))?;
+        Ok(RateLimit(limit))
+    }
+}
+
+/// An MSS clamp, "auto" or the MSS, same as `--mss-clamp`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Mss(pub MssClamp);
+
+impl TryFrom<String> for Mss {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let clamp = s
+            .parse()
+            .map_err(|_| format!("invalid mss clamp {:?}", s))?;
+        Ok(Mss(clamp))
+    }
+}
+
+/// Rate limits of a client group in the form of "network/prefix=ingress[,egress]",
+/// same as `--client-limit`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String")]
+pub struct ClientLimit(pub IpNet, pub Option<Limit>, pub Option<Limit>);
+
+impl TryFrom<String> for ClientLimit {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let (net, ingress, egress) =
+            parse_client_limit(&s).map_err(|_| format!("invalid client limit {:?}", s))?;
+        Ok(ClientLimit(net, ingress, egress))
+    }
+}
+
+/// An ACL rule, same as `--acl`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String")]
+pub struct AclRule(pub acl::Rule);
+
+impl TryFrom<String> for AclRule {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let rule = s.parse().map_err(|_| format!("invalid acl rule {:?}", s))?;
+        Ok(AclRule(rule))
+    }
+}
+
+#[cfg(feature = "holepunch")]
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Rndz {
+    pub server: Vec<String>,
+    pub local_id: String,
+    pub remote_id: Option<String>,
+}
+
+/// Configuration file, keys are named after the long command line options.
+///
+/// Multiple tunnels are declared as `[tunnel.<name>]` tables, the top level
+/// keys are shared by all of them.
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct File {
+    pub local: Option<SocketAddr>,
+    pub remote: Option<Vec<String>>,
+    pub ifname: Option<String>,
+    pub mtu: Option<u16>,
+    pub fragment: Option<u16>,
+    pub pmtu_discovery: Option<PmtuPolicy>,
+    pub mss_clamp: Option<Mss>,
+    pub ipv4_addr: Option<Ipv4Net>,
+    pub ipv6_addr: Option<Ipv6Net>,
+    pub daemon: Option<bool>,
+    pub key: Option<String>,
+    #[serde(rename = "type")]
+    pub cipher: Option<String>,
+    pub route: Option<Vec<Route>>,
+    pub reconnect_timeo: Option<u64>,
+    pub rebind_timeo: Option<u64>,
+    pub client_timeo: Option<u64>,
+    pub keepalive: Option<u64>,
+    pub table: Option<String>,
+    pub metric: Option<String>,
+    pub fwmark: Option<u32>,
+    pub default_route: Option<bool>,
+    pub tap: Option<bool>,
+    pub netns: Option<String>,
+    pub masquerade: Option<String>,
+    pub up: Option<String>,
+    pub down: Option<String>,
+    pub client_connect: Option<String>,
+    pub client_disconnect: Option<String>,
+    pub push_route: Option<Vec<IpNet>>,
+    pub push_dns: Option<Vec<IpAddr>>,
+    pub push_domain: Option<Vec<String>>,
+    pub accept_dns: Option<bool>,
+    pub accept_routes: Option<bool>,
+    pub multicast: Option<Vec<IpNet>>,
+    pub broadcast: Option<bool>,
+    pub client_to_client: Option<ClientPolicy>,
+    pub client_group: Option<Vec<ClientGroup>>,
+    pub acl: Option<Vec<AclRule>>,
+    pub acl_default: Option<acl::Action>,
+    pub strict_source: Option<bool>,
+    pub vip_policy: Option<VipPolicy>,
+    pub ingress_limit: Option<RateLimit>,
+    pub egress_limit: Option<RateLimit>,
+    pub client_limit: Option<Vec<ClientLimit>>,
+    pub max_clients: Option<usize>,
+    pub max_clients_per_ip: Option<usize>,
+    pub max_vips: Option<usize>,
+    pub admission: Option<AdmissionPolicy>,
+    pub state_file: Option<PathBuf>,
+    pub wait_dns: Option<bool>,
+    pub rebind: Option<bool>,
+    pub pre_resolve_dns: Option<bool>,
+    #[cfg(feature = "holepunch")]
+    pub rndz: Option<Rndz>,
+    pub tunnel: Option<BTreeMap<String, File>>,
+}
+
+impl File {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path)
+            .map_err(|e| Error::InvalidArg(format!("{}: {}", path.display(), e)))?;
+
+        Self::parse(&s).map_err(|e| Error::InvalidArg(format!("{}:{}", path.display(), e)))
+    }
+
+    // Parses the content, the error is prefixed with "line:column" of the offending key.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        toml::from_str(s).map_err(|e| {
+            let (line, col, text) = match e.span() {
+                Some(span) => {
+                    let line = s[..span.start].matches('\n').count();
+                    let col = span.start - s[..span.start].rfind('\n').map_or(0, |i| i + 1);
+                    (line + 1, col + 1, s.lines().nth(line).unwrap_or("").trim())
+                }
+                None => (0, 0, ""),
+            };
+            format!("{}:{}: {} in `{}`", line, col, e.message().trim(), text)
+        })
+    }
+
+    pub fn tunnel_names(&self) -> Vec<String> {
+        self.tunnel
+            .as_ref()
+            .map(|t| t.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Applies the tunnel table, the interface is named after the tunnel by default.
+    pub fn apply_tunnel(&self, name: &str, config: &mut Config) -> Result<(), Error> {
+        let tunnel = self
+            .tunnel
+            .as_ref()
+            .and_then(|t| t.get(name))
+            .ok_or_else(|| Error::InvalidArg(format!("tunnel {:?} not found", name)))?;
+        if tunnel.tunnel.is_some() {
+            return Err(Error::InvalidArg(format!(
+                "tunnel {:?} can't contain tunnels",
+                name
+            )));
+        }
+
+        config.ifname = Some(name.to_owned());
+        tunnel.apply(config)
+    }
+
+    pub fn apply(&self, config: &mut Config) -> Result<(), Error> {
+        if let Some(local) = self.local {
+            config.listen_addr = Some(local);
+        }
+
+        if let Some(ref remote) = self.remote {
+            config.server_addrs = Some(remote.clone());
+        }
+
+        #[cfg(feature = "holepunch")]
+        if let Some(ref r) = self.rndz {
+            config.rndz = Some(rndz::Config {
+                servers: r.server.clone(),
+                local_id: r.local_id.clone(),
+                remote_id: r.remote_id.clone(),
+            });
+        }
+
+        if let Some(ref ifname) = self.ifname {
+            config.ifname = Some(ifname.clone());
+        }
+
+        if let Some(mtu) = self.mtu {
+            config.mtu = mtu;
+        }
+
+        if let Some(v) = self.fragment {
+            config.fragment = Some(v);
+        }
+
+        if let Some(v) = self.pmtu_discovery {
+            config.pmtu_discovery = v;
+        }
+
+        if let Some(ref v) = self.mss_clamp {
+            config.mss_clamp = Some(v.0);
+        }
+
+        if let Some(addr4) = self.ipv4_addr {
+            config.loc_tun_in = Some(addr4);
+        }
+
+        if let Some(addr6) = self.ipv6_addr {
+            config.loc_tun_in6 = Some(addr6);
+        }
+
+        if let Some(ref key) = self.key {
+            let cipher = self.cipher.as_deref().unwrap_or(DEFAULT_CIPHER);
+            config.cryptor = cryptor::Builder::new(key, cipher)
+                .map_err(|_| Error::InvalidArg(format!("invalid `type` {:?}", cipher)))?
+                .build();
+        }
+
+        if let Some(daemon) = self.daemon {
+            config.daemonize = daemon;
+        }
+
+        if let Some(ref routes) = self.route {
+            config.routes = routes.iter().map(|r| (r.0, r.1, r.2)).collect();
+        }
+
+        if let Some(v) = self.keepalive {
+            config.keepalive_interval = Duration::from_secs(v);
+        }
+
+        if let Some(v) = self.reconnect_timeo {
+            config.reconnect_timeout = Duration::from_secs(v);
+        }
+
+        if let Some(v) = self.rebind_timeo {
+            config.rebind_timeout = Duration::from_secs(v);
+        }
+
+        if let Some(v) = self.client_timeo {
+            config.client_timeout = Duration::from_secs(v);
+        }
+
+        if let Some(ref table) = self.table {
+            config.table = Some(table.clone());
+        }
+
+        if let Some(ref metric) = self.metric {
+            config.metric = Some(metric.clone());
+        }
+
+        if let Some(fwmark) = self.fwmark {
+            config.fwmark = Some(fwmark);
+        }
+
+        if let Some(v) = self.default_route {
+            config.default_route = v;
+        }
+
+        if let Some(v) = self.tap {
+            config.tap = v;
+        }
+
+        if let Some(ref v) = self.netns {
+            config.netns = Some(v.clone());
+        }
+
+        if let Some(ref v) = self.masquerade {
+            config.masquerade = Some(v.clone());
+        }
+
+        if let Some(ref v) = self.up {
+            config.up = Some(v.clone());
+        }
+
+        if let Some(ref v) = self.down {
+            config.down = Some(v.clone());
+        }
+
+        if let Some(ref v) = self.client_connect {
+            config.client_connect = Some(v.clone());
+        }
+
+        if let Some(ref v) = self.client_disconnect {
+            config.client_disconnect = Some(v.clone());
+        }
+
+        if let Some(ref v) = self.push_route {
+            config.push_route = v.clone();
+        }
+
+        if let Some(ref v) = self.push_dns {
+            config.push_dns = v.clone();
+        }
+
+        if let Some(ref v) = self.push_domain {
+            config.push_domain = v.clone();
+        }
+
+        if let Some(v) = self.accept_dns {
+            config.accept_dns = v;
+        }
+
+        if let Some(v) = self.accept_routes {
+            config.accept_routes = v;
+        }
+
+        if let Some(ref v) = self.multicast {
+            config.multicast = v.clone();
+        }
+
+        if let Some(v) = self.broadcast {
+            config.broadcast = v;
+        }
+
+        if let Some(v) = self.client_to_client {
+            config.client_to_client = v;
+        }
+
+        if let Some(ref groups) = self.client_group {
+            config.client_groups = groups.iter().map(|g| (g.0, g.1)).collect();
+        }
+
+        if let Some(ref rules) = self.acl {
+            config.acl = rules.iter().map(|r| r.0.clone()).collect();
+        }
+
+        if let Some(v) = self.acl_default {
+            config.acl_default = v;
+        }
+
+        if let Some(v) = self.strict_source {
+            config.strict_source = v;
+        }
+
+        if let Some(v) = self.vip_policy {
+            config.vip_policy = v;
+        }
+
+        if let Some(ref v) = self.ingress_limit {
+            config.ingress_limit = Some(v.0);
+        }
+
+        if let Some(ref v) = self.egress_limit {
+            config.egress_limit = Some(v.0);
+        }
+
+        if let Some(ref limits) = self.client_limit {
+            config.client_limits = limits.iter().map(|l| (l.0, l.1, l.2)).collect();
+        }
+
+        if let Some(v) = self.max_clients {
+            config.max_clients = Some(v);
+        }
+
+        if let Some(v) = self.max_clients_per_ip {
+            config.max_clients_per_ip = Some(v);
+        }
+
+        if let Some(v) = self.max_vips {
+            config.max_vips = Some(v);
+        }
+
+        if let Some(v) = self.admission {
+            config.admission = v;
+        }
+
+        if let Some(ref v) = self.state_file {
+            config.state_file = Some(v.clone());
+        }
+
+        if let Some(v) = self.wait_dns {
+            config.wait_dns = v;
+        }
+
+        if let Some(v) = self.rebind {
+            config.rebind = v;
+        }
+
+        if let Some(v) = self.pre_resolve_dns {
+            config.pre_resolve_dns = v;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    #[test]
+    fn test() {
+        let file = File::parse(
+            r#"
+            remote = ["1.2.3.4:1234", "[::1]:1234"]
+            ipv4-addr = "10.0.0.2/24"
+            key = "helloworld"
+            type = "aes-256"
+            route = ["192.168.0.0/16", "172.16.0.0/12=10.0.0.1"]
+            keepalive = 10
+            "#,
+        )
+        .unwrap();
+
+        let mut config = Config::new();
+        file.apply(&mut config).unwrap();
+        assert_eq!(config.server_addrs.as_ref().unwrap().len(), 2);
+        assert_eq!(config.loc_tun_in, Some("10.0.0.2/24".parse().unwrap()));
+        assert!(config.cryptor.is_some());
+        assert_eq!(config.routes[1].1, Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(config.keepalive_interval, Duration::from_secs(10));
+
+        let e = File::parse("mtu = 1300\nroute = [\"10.0.0.0/33\"]\n").unwrap_err();
+        assert!(e.starts_with("2:9:"), "{}", e);
+        assert!(e.contains("route"), "{}", e);
+
+        let e = File::parse("mtu = 1300\nmtuu = 1300\n").unwrap_err();
+        assert!(e.starts_with("2:1:"), "{}", e);
+    }
+
+    #[test]
+    fn test_tunnels() {
+        let file = File::parse(
+            r#"
+            key = "helloworld"
+            [tunnel.office]
+            remote = ["1.2.3.4:1234"]
+            [tunnel.gw]
+            local = "0.0.0.0:1234"
+            ifname = "gw0"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(file.tunnel_names(), ["gw", "office"]);
+
+        let mut config = Config::new();
+        file.apply(&mut config).unwrap();
+        file.apply_tunnel("office", &mut config).unwrap();
+        assert_eq!(config.ifname.as_deref(), Some("office"));
+        assert!(config.is_client());
+        assert!(config.cryptor.is_some());
+
+        let mut config = Config::new();
+        file.apply_tunnel("gw", &mut config).unwrap();
+        assert_eq!(config.ifname.as_deref(), Some("gw0"));
+
+        assert!(file.apply_tunnel("home", &mut Config::new()).is_err());
+    }
+}
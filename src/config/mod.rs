@@ -1,5 +1,11 @@
 mod common;
-pub use common::Config;
+pub use common::{
+    parse_client_group, parse_client_limit, parse_route, AdmissionPolicy, ClientPolicy, Config,
+    Diff, MssClamp, PmtuPolicy, VipPolicy, DEFAULT_CIPHER,
+};
+
+mod file;
+pub use file::File;
 
 #[cfg(feature = "holepunch")]
 pub mod rndz;
@@ -1,4 +1,4 @@
-#[derive(Default, Clone)]
+#[derive(Default, Clone, PartialEq)]
 pub struct Config {
     pub servers: Vec<String>,
     pub local_id: String,
@@ -0,0 +1,160 @@
+//! DNS configuration pushed by the server and applied on the client.
+
+use crate::error::Error;
+use crate::util::with_suffix;
+use log::info;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+/// The resolv.conf of the host.
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct Dns {
+    pub servers: Vec<IpAddr>,
+    pub search: Vec<String>,
+}
+
+impl Dns {
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty() && self.search.is_empty()
+    }
+}
+
+/// A backend applying the pushed DNS configuration to the system.
+pub trait Resolver {
+    /// Applies the configuration, may be called again when it changes.
+    fn apply(&self, dns: &Dns) -> Result<(), Error>;
+    /// Restores the configuration from before the first `apply`.
+    fn restore(&self) -> Result<(), Error>;
+}
+
+/// Rewrites resolv.conf. The original file, which may be a symlink such as the stub of
+/// systemd-resolved, is moved aside to `<path>.minivtun` and moved back on restore, so
+/// it is neither written through nor lost if the process dies. A missing original, as
+/// in `/etc/netns/<name>`, is kept as an empty backup and removed again on restore.
+pub struct ResolvConf {
+    path: PathBuf,
+    backup: PathBuf,
+}
+
+impl Default for ResolvConf {
+    fn default() -> Self {
+        Self::new(RESOLV_CONF)
+    }
+}
+
+impl ResolvConf {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        Self {
+            backup: with_suffix(&path, ".minivtun"),
+            path,
+        }
+    }
+
+    // The pushed servers and domains, `options` of the original file are kept.
+    fn render(dns: &Dns, orig: &str) -> String {
+        let mut s = String::from("# Generated by minivtun-rs\n");
+        if !dns.search.is_empty() {
+            s += &format!("search {}\n", dns.search.join(" "));
+        }
+        for server in &dns.servers {
+            s += &format!("nameserver {}\n", server);
+        }
+        for line in orig
+            .lines()
+            .filter(|l| l.trim_start().starts_with("options"))
+        {
+            s += line;
+            s += "\n";
+        }
+        s
+    }
+}
+
+impl Resolver for ResolvConf {
+    fn apply(&self, dns: &Dns) -> Result<(), Error> {
+        // a backup left by an earlier run is the original
+        let orig = match fs::read_to_string(&self.backup) {
+            Ok(orig) => orig,
+            Err(_) => fs::read_to_string(&self.path).unwrap_or_default(),
+        };
+        let tmp = with_suffix(&self.path, ".tmp");
+        fs::write(&tmp, Self::render(dns, &orig))?;
+
+        if fs::symlink_metadata(&self.backup).is_err() {
+            match fs::rename(&self.path, &self.backup) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => fs::write(&self.backup, "")?,
+                result => result?,
+            }
+        }
+        fs::rename(&tmp, &self.path)?;
+        info!("apply dns {:?} to {}", dns, self.path.display());
+        Ok(())
+    }
+
+    fn restore(&self) -> Result<(), Error> {
+        if fs::symlink_metadata(&self.backup).is_ok_and(|v| v.is_file() && v.len() == 0) {
+            match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e)?,
+                _ => {}
+            }
+            fs::remove_file(&self.backup)?;
+            info!("remove {}", self.path.display());
+            return Ok(());
+        }
+        match fs::rename(&self.backup, &self.path) {
+            Ok(()) => info!("restore {}", self.path.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => Err(e)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    #[test]
+    fn test() {
+        let dir = std::env::temp_dir().join(format!("minivtun.dns.{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (path, stub) = (dir.join("resolv.conf"), dir.join("stub-resolv.conf"));
+        let orig = "nameserver 192.168.1.1\noptions edns0\n";
+        fs::write(&stub, orig).unwrap();
+        std::os::unix::fs::symlink("stub-resolv.conf", &path).unwrap();
+
+        let resolver = ResolvConf::new(&path);
+        let dns = Dns {
+            servers: vec!["10.0.0.1".parse().unwrap()],
+            search: vec!["corp.example.com".into()],
+        };
+        resolver.apply(&dns).unwrap();
+        resolver.apply(&dns).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Generated by minivtun-rs\nsearch corp.example.com\nnameserver 10.0.0.1\noptions edns0\n"
+        );
+        // the symlink is replaced, not written through
+        assert_eq!(fs::read_to_string(&stub).unwrap(), orig);
+
+        // restored by another instance, as after a crash
+        ResolvConf::new(&path).restore().unwrap();
+        assert!(fs::symlink_metadata(&path).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&path).unwrap(), orig);
+        resolver.restore().unwrap();
+
+        // none to begin with
+        let path = dir.join("netns.conf");
+        let resolver = ResolvConf::new(&path);
+        resolver.apply(&dns).unwrap();
+        assert!(path.exists());
+        resolver.restore().unwrap();
+        assert!(!path.exists() && !resolver.backup.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
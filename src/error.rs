@@ -7,10 +7,16 @@ pub enum Error {
     TunInitialization(tun::Error),
     InvalidArg(String),
     InvalidPacket,
+    ShortPacket,
     EncryptFail,
     DecryptFail,
+    AuthFail,
+    #[deprecated(note = "netlink failures are reported as `Error::Netlink`")]
     AddAddrFail,
+    #[deprecated(note = "netlink failures are reported as `Error::Netlink`")]
     AddRouteFail,
+    Netlink(String, std::io::Error),
+    Netns(String, std::io::Error),
     NoRoute(String),
     Io(#[from] std::io::Error),
     Packet(#[from] packet::Error),
@@ -1,17 +1,23 @@
 use clap::{App, Arg};
-use ipnet::IpNet;
 #[cfg(feature = "holepunch")]
 use minivtun::config::rndz;
+use minivtun::config::{self, parse_client_group, parse_client_limit, parse_route, DEFAULT_CIPHER};
 use minivtun::{cryptor, Config, Error};
-use std::{
-    net::{IpAddr, ToSocketAddrs},
-    result::Result,
-    time::Duration,
-};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::{net::ToSocketAddrs, result::Result, time::Duration};
 
-const DEFAULT_CIPHER: &str = "aes-128";
+// Relative paths are resolved against the directory the process was started in,
+// as a daemon changes to "/" before the config is reloaded.
+static START_DIR: LazyLock<PathBuf> = LazyLock::new(|| std::env::current_dir().unwrap_or_default());
 
-pub(crate) fn parse(config: &mut Config) -> Result<(), Error> {
+fn absolute<P: AsRef<Path>>(path: P) -> PathBuf {
+    START_DIR.join(path)
+}
+
+// Parses the command line, options of the tunnel table in the config file are
+// applied if `tunnel` is set.
+pub(crate) fn parse(config: &mut Config, tunnel: Option<&str>) -> Result<(), Error> {
     let default_mtu = config.mtu.to_string();
     let default_reconnect_timeo = config.reconnect_timeout.as_secs().to_string();
     let default_rebind_timeo = config.rebind_timeout.as_secs().to_string();
@@ -21,15 +27,19 @@ pub(crate) fn parse(config: &mut Config) -> Result<(), Error> {
     let app = App::new("minivtun-rs")
         .version(env!("CARGO_PKG_VERSION"))
         .about("Mini virtual tunneller in non-standard protocol")
+        .arg(Arg::from_usage("-f, --config [file]                 'load configuration from TOML file, overridden by command line options'"))
         .arg(Arg::from_usage("-l, --local [ip:port] 'local IP:port for server to listen'"))
         .arg(Arg::from_usage("-r, --remote... [host:port]         'host:port of servers to connect (brace with [] for bare IPv6)'"))
         .arg(Arg::from_usage("-n, --ifname [ifname]               'virtual interface name'"))
         .arg(Arg::from_usage( "-m, --mtu [mtu]             'mtu size'").default_value(&default_mtu))
+        .arg(Arg::from_usage("    --fragment [size]               'split packets into tunnel datagrams of at most size bytes, peers must support it'"))
+        .arg(Arg::from_usage("    --pmtu-discovery [policy]       'whether the path MTU is probed, and the interface MTU fitted to it (client only)'").possible_values(&["off", "probe", "adjust"]))
+        .arg(Arg::from_usage("    --mss-clamp [mss]               'clamp the MSS of TCP SYN segments through the tunnel, auto to fit the MTU'"))
         .arg(Arg::from_usage("-a, --ipv4-addr [tun_lip/prf_len]   'pointopoint IPv4 pair of the virtual interface'"))
         .arg(Arg::from_usage("-A, --ipv6-addr [tun_ip6/pfx_len]   IPv6 address/prefix length pair"))
         .arg(Arg::from_usage("-d, --daemon                        'run as daemon process'"))
         .arg(Arg::from_usage("-e, --key [encryption_key]          'shared password for data encryption'"))
-        .arg(Arg::from_usage("-v, --route... [network/prefix[=gw]]  'attached IPv4/IPv6 route on this link, can be multiple'"))
+        .arg(Arg::from_usage("-v, --route... [network/prefix[=gw][@metric]]  'attached IPv4/IPv6 route on this link, can be multiple'"))
         .arg(Arg::from_usage("-t, --type [encryption_type]        'encryption type'").default_value(DEFAULT_CIPHER).possible_values(&["plain", "aes-128", "aes-256"]))
         .arg(Arg::from_usage("-R, --reconnect-timeo [N]           'maximum inactive time (seconds) before reconnect'").default_value(&default_reconnect_timeo))
         .arg(Arg::from_usage("    --rebind-timeo [N]              'maximum time (seconds) before rebind'").default_value(&default_rebind_timeo))
@@ -39,10 +49,41 @@ pub(crate) fn parse(config: &mut Config) -> Result<(), Error> {
         .arg(Arg::from_usage("-T, --table [table_name]            'route table of the attached routes'"))
         .arg(Arg::from_usage("-M, --metric [metric]               'metric of attached routes'"))
         .arg(Arg::from_usage("-F, --fwmark [fwmark_num]           'fwmark set on vpn traffic'"))
+        .arg(Arg::from_usage("-D, --default-route                 'route all traffic through the tunnel (client only)'"))
+        .arg(Arg::from_usage("    --tap                           'carry Ethernet frames over a TAP interface, peers must agree'"))
+        .arg(Arg::from_usage("    --netns [name|path]             'network namespace of the virtual interface, the socket stays in the current one'"))
+        .arg(Arg::from_usage("    --masquerade [out_iface]        'forward and masquerade the tunnel subnets out of the interface (server only)'"))
+        .arg(Arg::from_usage("    --up [cmd]                      'shell command to run after the interface is configured'"))
+        .arg(Arg::from_usage("    --down [cmd]                    'shell command to run after the tunnel is shut down'"))
+        .arg(Arg::from_usage("    --client-connect [cmd]          'shell command to run when a client virtual address appears (server only)'"))
+        .arg(Arg::from_usage("    --client-disconnect [cmd]       'shell command to run when a client virtual address is gone (server only)'"))
+        .arg(Arg::from_usage("    --push-route... [network/prefix]  'route pushed to clients and installed on their interface (server only)'"))
+        .arg(Arg::from_usage("    --push-dns... [ip]              'DNS server pushed to clients (server only)'"))
+        .arg(Arg::from_usage("    --push-domain... [domain]       'DNS search domain pushed to clients (server only)'"))
+        .arg(Arg::from_usage("    --multicast... [group/prefix]   'multicast groups replicated to all clients (server only)'"))
+        .arg(Arg::from_usage("    --broadcast                     'replicate broadcast of the tunnel subnet to all clients (server only)'"))
+        .arg(Arg::from_usage("    --client-to-client [policy]     'traffic between clients is forwarded by the kernel, sent directly or dropped (server only)'").possible_values(&["kernel", "hairpin", "isolate"]))
+        .arg(Arg::from_usage("    --client-group... [network/prefix=policy]  'client-to-client policy of the clients in the network, can be multiple (server only)'"))
+        .arg(Arg::from_usage("    --acl... [rule]                 'allow|deny [from net] [to net] [proto tcp|udp|icmp] [port n[-m]], first match wins (server only)'"))
+        .arg(Arg::from_usage("    --acl-default [action]          'action of packets matching no acl rule (server only)'").possible_values(&["allow", "deny"]))
+        .arg(Arg::from_usage("    --strict-source                 'drop packets from a client with a source other than its virtual addresses and routes (server only)'"))
+        .arg(Arg::from_usage("    --vip-policy [policy]           'whether a virtual address moves to any client claiming it or stays bound to its client (server only)'").possible_values(&["roam", "bind"]))
+        .arg(Arg::from_usage("    --ingress-limit [rate[:burst]]   'bytes per second each client may send, k, m and g suffixes are powers of 1000 (server only)'"))
+        .arg(Arg::from_usage("    --egress-limit [rate[:burst]]    'bytes per second sent to each client (server only)'"))
+        .arg(Arg::from_usage("    --client-limit... [network/prefix=ingress[,egress]]  'rate limits of the clients in the network, none for unlimited, can be multiple (server only)'"))
+        .arg(Arg::from_usage("    --max-clients [N]               'maximum number of client addresses (server only)'"))
+        .arg(Arg::from_usage("    --max-clients-per-ip [N]        'maximum number of client addresses of a source IP (server only)'"))
+        .arg(Arg::from_usage("    --max-vips [N]                  'maximum number of client virtual addresses (server only)'"))
+        .arg(Arg::from_usage("    --admission [policy]            'whether a new client or vip over a limit is rejected or evicts the oldest idle one (server only)'").possible_values(&["reject", "evict"]))
+        .arg(Arg::from_usage("    --state-file [path]             'file the clients are saved to and restored from across restarts (server only)'"))
+        .arg(Arg::from_usage("    --accept-dns                    'apply DNS pushed by the server to /etc/resolv.conf (client only)'"))
+        .arg(Arg::from_usage("    --accept-routes                 'install routes pushed by the server on the interface (client only)'"))
         .arg(Arg::from_usage("-w, --wait-dns                      'wait for DNS resolve ready after service started'"))
         .arg(Arg::from_usage("    --rebind                        'rebind socket before reconnect'"))
         .arg(Arg::from_usage("-i, --info                          'view current tunnel info'"))
         .arg(Arg::from_usage("-c, --change-server                 'trigger client to change server'"))
+        .arg(Arg::from_usage("    --reload                        'trigger reload of the configuration, same as SIGHUP'"))
+        .arg(Arg::from_usage("    --tunnel [name]                 'tunnel of the config file to view or control'"))
         .arg(Arg::from_usage("    --pre-resolve-dns               'resolve dns at start and save for reconnect'"))
         ;
     #[cfg(feature = "holepunch")]
@@ -60,6 +101,19 @@ pub(crate) fn parse(config: &mut Config) -> Result<(), Error> {
 
     let matches = app.get_matches();
 
+    // options with default value only override the config file if explicitly set
+    let explicit = |name| matches.occurrences_of(name) > 0;
+
+    let config_file = matches.value_of("config").map(absolute);
+    let file = config_file.as_ref().map(config::File::load).transpose()?;
+    if let Some(ref file) = file {
+        file.apply(config)?;
+        if let Some(name) = tunnel {
+            file.apply_tunnel(name, config)?;
+        }
+    }
+    config.config_file = config_file;
+
     if let Some(local) = matches.value_of("local") {
         config.listen_addr = Some(
             local
@@ -88,14 +142,33 @@ pub(crate) fn parse(config: &mut Config) -> Result<(), Error> {
         });
     }
 
-    config.ifname = Some(matches.value_of("ifname").unwrap_or("mv%d").into());
+    if let Some(ifname) = matches.value_of("ifname") {
+        config.ifname = Some(ifname.into());
+    } else if config.ifname.is_none() {
+        config.ifname = Some("mv%d".into());
+    }
 
-    if let Some(v) = matches.value_of("mtu") {
+    if let Some(v) = matches.value_of("mtu").filter(|_| explicit("mtu")) {
         config.mtu = v
             .parse()
             .map_err(|_| Error::InvalidArg("invalid mtu".into()))?;
     }
 
+    if let Some(v) = matches.value_of("fragment") {
+        config.fragment = Some(
+            v.parse()
+                .map_err(|_| Error::InvalidArg("invalid fragment".into()))?,
+        );
+    }
+
+    if let Some(policy) = matches.value_of("pmtu-discovery") {
+        config.pmtu_discovery = policy.parse()?;
+    }
+
+    if let Some(v) = matches.value_of("mss-clamp") {
+        config.mss_clamp = Some(v.parse()?);
+    }
+
     if let Some(addr4) = matches.value_of("ipv4-addr") {
         config.loc_tun_in = Some(
             addr4
@@ -112,63 +185,87 @@ pub(crate) fn parse(config: &mut Config) -> Result<(), Error> {
         );
     }
 
-    if let (Some(t), Some(key)) = (matches.value_of("type"), matches.value_of("key")) {
-        config.cryptor = cryptor::Builder::new(key, t)
-            .map_err(|_| Error::InvalidArg("invalid encryption type".into()))?
-            .build();
+    if explicit("key") || explicit("type") {
+        // the tunnel table takes precedence over the top level
+        let sections: Vec<&config::File> = file
+            .iter()
+            .flat_map(|f| {
+                tunnel
+                    .and_then(|name| f.tunnel.as_ref()?.get(name))
+                    .into_iter()
+                    .chain([f])
+            })
+            .collect();
+        let key = matches
+            .value_of("key")
+            .or_else(|| sections.iter().find_map(|f| f.key.as_deref()));
+        let t = matches
+            .value_of("type")
+            .filter(|_| explicit("type"))
+            .or_else(|| sections.iter().find_map(|f| f.cipher.as_deref()))
+            .unwrap_or(DEFAULT_CIPHER);
+        if let Some(key) = key {
+            config.cryptor = cryptor::Builder::new(key, t)
+                .map_err(|_| Error::InvalidArg("invalid encryption type".into()))?
+                .build();
+        }
     }
 
-    config.daemonize = matches.is_present("daemon");
+    if matches.is_present("daemon") {
+        config.daemonize = true;
+    }
 
     if let Some(routes) = matches.values_of("route") {
-        for r in routes {
-            let mut parts = r.splitn(2, '=');
-            let net: IpNet = parts
-                .next()
-                .ok_or(Error::InvalidArg("Invalid route network".into()))?
-                .parse()
-                .map_err(|_| Error::InvalidArg("invalid route".into()))?;
-            let gw: Option<IpAddr> = parts
-                .next()
-                .map(|gw| {
-                    gw.parse()
-                        .map_err(|_| Error::InvalidArg("invalid gateway".into()))
-                })
-                .transpose()?;
-            config.routes.push((net, gw));
-        }
+        config.routes = routes.map(parse_route).collect::<Result<_, _>>()?;
     }
 
-    if let Some(v) = matches.value_of("keepalive") {
+    if let Some(v) = matches
+        .value_of("keepalive")
+        .filter(|_| explicit("keepalive"))
+    {
         config.keepalive_interval = Duration::from_secs(
             v.parse()
                 .map_err(|_| Error::InvalidArg("keepalive".into()))?,
         );
     }
 
-    if let Some(v) = matches.value_of("reconnect-timeo") {
+    if let Some(v) = matches
+        .value_of("reconnect-timeo")
+        .filter(|_| explicit("reconnect-timeo"))
+    {
         config.reconnect_timeout = Duration::from_secs(
             v.parse()
                 .map_err(|_| Error::InvalidArg("reconnect-timeo".into()))?,
         );
     }
 
-    if let Some(v) = matches.value_of("rebind-timeo") {
+    if let Some(v) = matches
+        .value_of("rebind-timeo")
+        .filter(|_| explicit("rebind-timeo"))
+    {
         config.rebind_timeout = Duration::from_secs(
             v.parse()
                 .map_err(|_| Error::InvalidArg("rebind-timeo".into()))?,
         );
     }
 
-    if let Some(v) = matches.value_of("client-timeo") {
+    if let Some(v) = matches
+        .value_of("client-timeo")
+        .filter(|_| explicit("client-timeo"))
+    {
         config.client_timeout = Duration::from_secs(
             v.parse()
                 .map_err(|_| Error::InvalidArg("client-timeo".into()))?,
         );
     }
 
-    config.table = matches.value_of("table").map(Into::into);
-    config.metric = matches.value_of("metric").map(Into::into);
+    if let Some(table) = matches.value_of("table") {
+        config.table = Some(table.into());
+    }
+
+    if let Some(metric) = matches.value_of("metric") {
+        config.metric = Some(metric.into());
+    }
 
     if let Some(fwmark) = matches.value_of("fwmark") {
         config.fwmark = Some(
@@ -178,12 +275,139 @@ pub(crate) fn parse(config: &mut Config) -> Result<(), Error> {
         );
     }
 
-    config.wait_dns = matches.is_present("wait-dns");
-    config.rebind = matches.is_present("rebind");
+    if let Some(netns) = matches.value_of("netns") {
+        config.netns = Some(netns.into());
+    }
+
+    if let Some(out) = matches.value_of("masquerade") {
+        config.masquerade = Some(out.into());
+    }
+
+    if let Some(cmd) = matches.value_of("up") {
+        config.up = Some(cmd.into());
+    }
+
+    if let Some(cmd) = matches.value_of("down") {
+        config.down = Some(cmd.into());
+    }
+
+    if let Some(cmd) = matches.value_of("client-connect") {
+        config.client_connect = Some(cmd.into());
+    }
+
+    if let Some(cmd) = matches.value_of("client-disconnect") {
+        config.client_disconnect = Some(cmd.into());
+    }
+
+    if let Some(routes) = matches.values_of("push-route") {
+        config.push_route = routes
+            .map(|v| {
+                v.parse()
+                    .map_err(|_| Error::InvalidArg(format!("invalid route {:?}", v)))
+            })
+            .collect::<Result<_, _>>()?;
+    }
+
+    if let Some(addrs) = matches.values_of("push-dns") {
+        config.push_dns = addrs
+            .map(|v| {
+                v.parse()
+                    .map_err(|_| Error::InvalidArg(format!("invalid dns server {:?}", v)))
+            })
+            .collect::<Result<_, _>>()?;
+    }
+
+    if let Some(domains) = matches.values_of("push-domain") {
+        config.push_domain = domains.map(String::from).collect();
+    }
+
+    if let Some(groups) = matches.values_of("multicast") {
+        config.multicast = groups
+            .map(|v| {
+                v.parse()
+                    .map_err(|_| Error::InvalidArg(format!("invalid multicast group {:?}", v)))
+            })
+            .collect::<Result<_, _>>()?;
+    }
+
+    if let Some(policy) = matches.value_of("client-to-client") {
+        config.client_to_client = policy.parse()?;
+    }
+
+    if let Some(groups) = matches.values_of("client-group") {
+        config.client_groups = groups.map(parse_client_group).collect::<Result<_, _>>()?;
+    }
+
+    if let Some(rules) = matches.values_of("acl") {
+        config.acl = rules.map(str::parse).collect::<Result<_, _>>()?;
+    }
+
+    if let Some(action) = matches.value_of("acl-default") {
+        config.acl_default = action.parse()?;
+    }
+
+    if let Some(policy) = matches.value_of("vip-policy") {
+        config.vip_policy = policy.parse()?;
+    }
+
+    if let Some(limit) = matches.value_of("ingress-limit") {
+        config.ingress_limit = Some(limit.parse()?);
+    }
+
+    if let Some(limit) = matches.value_of("egress-limit") {
+        config.egress_limit = Some(limit.parse()?);
+    }
+
+    if let Some(limits) = matches.values_of("client-limit") {
+        config.client_limits = limits.map(parse_client_limit).collect::<Result<_, _>>()?;
+    }
+
+    if let Some(v) = matches.value_of("max-clients") {
+        config.max_clients = Some(
+            v.parse()
+                .map_err(|_| Error::InvalidArg("invalid max-clients".into()))?,
+        );
+    }
+
+    if let Some(v) = matches.value_of("max-clients-per-ip") {
+        config.max_clients_per_ip = Some(
+            v.parse()
+                .map_err(|_| Error::InvalidArg("invalid max-clients-per-ip".into()))?,
+        );
+    }
+
+    if let Some(v) = matches.value_of("max-vips") {
+        config.max_vips = Some(
+            v.parse()
+                .map_err(|_| Error::InvalidArg("invalid max-vips".into()))?,
+        );
+    }
+
+    if let Some(policy) = matches.value_of("admission") {
+        config.admission = policy.parse()?;
+    }
+
+    if let Some(path) = matches.value_of("state-file") {
+        config.state_file = Some(path.into());
+    }
+    config.state_file = config.state_file.take().map(absolute);
+
+    config.strict_source |= matches.is_present("strict-source");
+    config.accept_dns |= matches.is_present("accept-dns");
+    config.accept_routes |= matches.is_present("accept-routes");
+    config.broadcast |= matches.is_present("broadcast");
+    config.default_route |= matches.is_present("default-route");
+    config.tap |= matches.is_present("tap");
+    config.wait_dns |= matches.is_present("wait-dns");
+    config.rebind |= matches.is_present("rebind");
     config.info = matches.is_present("info");
     config.change_server = matches.is_present("change-server");
+    config.reload = matches.is_present("reload");
+    config.tunnel = matches.value_of("tunnel").map(Into::into);
+
+    config.check_fragment()?;
 
-    config.pre_resolve_dns = matches.is_present("pre-resolve-dns");
+    config.pre_resolve_dns |= matches.is_present("pre-resolve-dns");
     if config.pre_resolve_dns {
         if let Some(ref mut addrs) = config.server_addrs {
             for v in addrs.iter_mut() {
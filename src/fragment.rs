@@ -0,0 +1,225 @@
+//! Tunnel level fragmentation of ip data messages larger than a datagram.
+//!
+//! An ip data message, the kind and the packet, is split in pieces sent as
+//! fragment messages of the same id. The receiver reassembles it once all the
+//! pieces arrived, incomplete ones are dropped after `TIMEOUT`.
+
+use crate::error::Error;
+use crate::msg::{FragmentPacket, FRAGMENT_HEADER_SIZE, IP_DATA_HEADER_SIZE, MSG_HEADER_SIZE};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+// messages being reassembled, of all peers and of one, the oldest is dropped
+// for a new one
+const MAX_PENDING: usize = 256;
+const MAX_PENDING_PER_SOURCE: usize = 16;
+// an ip data header and the largest packet
+const MAX_SIZE: usize = 4 + 65535;
+// the ciphers pad to their block size
+const BLOCK_SIZE: usize = 16;
+// the ethernet header of a frame in tap mode
+const ETHER_HEADER_SIZE: usize = 14;
+
+// Bytes of a message in a piece sent in datagrams of `size` bytes.
+fn room(size: u16) -> usize {
+    (size as usize / BLOCK_SIZE * BLOCK_SIZE).saturating_sub(MSG_HEADER_SIZE + FRAGMENT_HEADER_SIZE)
+}
+
+/// Checks that a packet of the `mtu` is split in no more pieces than the count
+/// of a fragment holds.
+pub fn check_size(size: u16, mtu: u16, tap: bool) -> Result<(), Error> {
+    let body = IP_DATA_HEADER_SIZE + mtu as usize + if tap { ETHER_HEADER_SIZE } else { 0 };
+    if room(size) == 0 || body.div_ceil(room(size)) > u8::MAX as usize {
+        return Err(Error::InvalidArg(format!(
+            "fragment {} too small for mtu {}",
+            size, mtu
+        )));
+    }
+    Ok(())
+}
+
+struct Pending {
+    parts: Vec<Option<Vec<u8>>>,
+    left: usize,
+    size: usize,
+    started: Instant,
+    // order of arrival, the oldest of a peer over its share is dropped
+    serial: u64,
+}
+
+#[derive(Default)]
+pub struct Fragments {
+    next_id: u16,
+    serial: u64,
+    pending: HashMap<(SocketAddr, u16), Pending>,
+}
+
+impl Fragments {
+    /// Splits the ip data message `body` in pieces sent in datagrams of at most
+    /// `size` bytes, with the id they share. A message needing more pieces than
+    /// the count holds is an error, see `check_size`.
+    pub fn split<'a>(&mut self, body: &'a [u8], size: u16) -> Result<(u16, Vec<&'a [u8]>), Error> {
+        let room = room(size);
+        if room == 0 || body.len().div_ceil(room) > u8::MAX as usize {
+            return Err(Error::InvalidPacket);
+        }
+
+        self.next_id = self.next_id.wrapping_add(1);
+        Ok((self.next_id, body.chunks(room).collect()))
+    }
+
+    /// Adds a piece from `src`, the message is returned once complete.
+    pub fn add<T: AsRef<[u8]>>(
+        &mut self,
+        src: SocketAddr,
+        frag: &FragmentPacket<T>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let (index, count) = (frag.index()? as usize, frag.count()? as usize);
+        let payload = frag.payload()?;
+        if index >= count {
+            Err(Error::InvalidPacket)?
+        }
+
+        let key = (src, frag.id()?);
+        let mismatch = self
+            .pending
+            .get(&key)
+            .map(|v| v.parts.len() != count || v.size + payload.len() > MAX_SIZE);
+        match mismatch {
+            Some(true) => {
+                self.pending.remove(&key);
+                Err(Error::InvalidPacket)?
+            }
+            Some(false) => {}
+            None => self.make_room(src),
+        }
+
+        self.serial += 1;
+        let serial = self.serial;
+        let pending = self.pending.entry(key).or_insert_with(|| Pending {
+            parts: vec![None; count],
+            left: count,
+            size: 0,
+            started: Instant::now(),
+            serial,
+        });
+        // a duplicate is ignored
+        if pending.parts[index].is_none() {
+            pending.parts[index] = Some(payload.to_vec());
+            pending.left -= 1;
+            pending.size += payload.len();
+        }
+        if pending.left > 0 {
+            return Ok(None);
+        }
+
+        let pending = self.pending.remove(&key).unwrap();
+        Ok(Some(
+            pending.parts.into_iter().flatten().flatten().collect(),
+        ))
+    }
+
+    // Drops the oldest message of `src` if it holds its whole share, so one peer
+    // can't take the room of the others, then the oldest of all if still full.
+    fn make_room(&mut self, src: SocketAddr) {
+        let own = self.pending.iter().filter(|(k, _)| k.0 == src);
+        let oldest = if own.clone().count() >= MAX_PENDING_PER_SOURCE {
+            own.min_by_key(|(_, v)| v.serial)
+        } else if self.pending.len() >= MAX_PENDING {
+            self.pending.iter().min_by_key(|(_, v)| v.serial)
+        } else {
+            None
+        };
+        if let Some(oldest) = oldest.map(|(k, _)| *k) {
+            self.pending.remove(&oldest);
+        }
+    }
+
+    /// Drops the messages pending for longer than `TIMEOUT`, returns how many.
+    pub fn expire(&mut self) -> usize {
+        self.expire_at(Instant::now())
+    }
+
+    fn expire_at(&mut self, now: Instant) -> usize {
+        let len = self.pending.len();
+        self.pending
+            .retain(|_, v| now.saturating_duration_since(v.started) <= TIMEOUT);
+        len - self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+    use crate::msg::{Builder, FragmentBuilder};
+
+    fn piece(id: u16, index: usize, count: usize, payload: &[u8]) -> FragmentPacket<Vec<u8>> {
+        let buf = FragmentBuilder::default()
+            .id(id)
+            .unwrap()
+            .index(index as u8, count as u8)
+            .unwrap()
+            .payload(payload)
+            .unwrap()
+            .build()
+            .unwrap();
+        FragmentPacket::new(buf).unwrap()
+    }
+
+    #[test]
+    fn test() {
+        let body: Vec<u8> = (0..3000u32).map(|v| v as u8).collect();
+        let mut fragments = Fragments::default();
+        let (id, pieces) = fragments.split(&body, 1300).unwrap();
+        assert_eq!(pieces.len(), 3);
+        assert!(pieces[0].len() + MSG_HEADER_SIZE + FRAGMENT_HEADER_SIZE <= 1300);
+        // never more than 255 pieces
+        assert!(fragments.split(&body, 0).is_err());
+        assert!(fragments.split(&body, 32).is_err());
+        assert!(check_size(1300, 65535, false).is_ok());
+        assert!(check_size(256, 65535, false).is_err());
+        assert!(check_size(16, 1500, true).is_err());
+
+        let src: SocketAddr = "1.2.3.4:5678".parse().unwrap();
+        let other: SocketAddr = "1.2.3.4:5679".parse().unwrap();
+        let frag = |i: usize| piece(id, i, 3, pieces[i]);
+        assert_eq!(fragments.add(src, &frag(2)).unwrap(), None);
+        assert_eq!(fragments.add(other, &frag(1)).unwrap(), None);
+        assert_eq!(fragments.add(src, &frag(0)).unwrap(), None);
+        assert_eq!(fragments.add(src, &frag(0)).unwrap(), None);
+        assert_eq!(fragments.add(src, &frag(1)).unwrap(), Some(body.clone()));
+
+        // a count not matching the first piece
+        assert!(fragments.add(other, &piece(id, 0, 2, &[0])).is_err());
+        assert!(fragments.add(other, &frag(0)).unwrap().is_none());
+
+        assert_eq!(fragments.expire(), 0);
+        assert_eq!(fragments.expire_at(Instant::now() + TIMEOUT * 2), 1);
+        assert!(fragments.pending.is_empty());
+
+        // a peer sending only first pieces holds its share at most
+        for id in 0..MAX_PENDING_PER_SOURCE as u16 + 4 {
+            assert!(fragments
+                .add(src, &piece(id, 0, 2, &[0]))
+                .unwrap()
+                .is_none());
+        }
+        let own = fragments.pending.keys().filter(|k| k.0 == src).count();
+        assert_eq!(own, MAX_PENDING_PER_SOURCE);
+        assert!(!fragments.pending.contains_key(&(src, 3)));
+        assert!(fragments.pending.contains_key(&(src, 4)));
+        let last = piece(MAX_PENDING_PER_SOURCE as u16 + 3, 1, 2, &[1]);
+        assert_eq!(fragments.add(src, &last).unwrap(), Some(vec![0, 1]));
+        assert!(fragments.add(other, &frag(0)).unwrap().is_none());
+
+        // many peers drop the oldest of all
+        for port in 0..MAX_PENDING as u16 {
+            let src = SocketAddr::from(([1, 2, 3, 5], port));
+            assert!(fragments.add(src, &frag(0)).unwrap().is_none());
+        }
+        assert_eq!(fragments.pending.len(), MAX_PENDING);
+        assert!(!fragments.pending.contains_key(&(src, 4)));
+    }
+}
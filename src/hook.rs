@@ -0,0 +1,151 @@
+//! Hook commands run on tunnel events, with the tunnel described in `MINIVTUN_*`
+//! environment variables.
+
+use crate::error::Error;
+use crate::Config;
+use log::{debug, warn};
+use std::net::{IpAddr, SocketAddr};
+use std::process::{Child, Command};
+
+pub type Env = Vec<(&'static str, String)>;
+
+/// Environment describing the tunnel on interface `ifname`.
+pub fn env(config: &Config, ifname: &str) -> Env {
+    let mut env = vec![
+        ("MINIVTUN_IFNAME", ifname.to_owned()),
+        ("MINIVTUN_MTU", config.mtu.to_string()),
+        (
+            "MINIVTUN_ROUTES",
+            config
+                .routes
+                .iter()
+                .map(|(net, gw, metric)| {
+                    let mut s = net.to_string();
+                    if let Some(gw) = gw {
+                        s += &format!("={}", gw);
+                    }
+                    if let Some(metric) = metric {
+                        s += &format!("@{}", metric);
+                    }
+                    s
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+    ];
+
+    if let Some(addr4) = config.loc_tun_in {
+        env.push(("MINIVTUN_IPV4_ADDR", addr4.to_string()));
+    }
+
+    if let Some(addr6) = config.loc_tun_in6 {
+        env.push(("MINIVTUN_IPV6_ADDR", addr6.to_string()));
+    }
+
+    if let Some(ref netns) = config.netns {
+        env.push(("MINIVTUN_NETNS", netns.clone()));
+    }
+
+    if let Some(local) = config.listen_addr {
+        env.push(("MINIVTUN_LOCAL", local.to_string()));
+    }
+
+    if let Some(ref remote) = config.server_addrs {
+        env.push(("MINIVTUN_REMOTE", remote.join(" ")));
+    }
+
+    env
+}
+
+/// Environment of a client event, `vip` is at the real address `addr`.
+pub fn client_env(config: &Config, ifname: &str, vip: IpAddr, addr: SocketAddr) -> Env {
+    let mut env = env(config, ifname);
+    env.push(("MINIVTUN_PEER_VIP", vip.to_string()));
+    env.push(("MINIVTUN_PEER_ADDR", addr.to_string()));
+    env
+}
+
+fn command(cmd: &str, event: &str, env: Env) -> Command {
+    let mut c = Command::new("sh");
+    c.arg("-c").arg(cmd).env("MINIVTUN_EVENT", event).envs(env);
+    c
+}
+
+/// Runs the hook and waits for it, fails if the command fails.
+pub fn run(cmd: &str, event: &str, env: Env) -> Result<(), Error> {
+    debug!("run {} hook: {}", event, cmd);
+    let status = command(cmd, event, env).status()?;
+    if !status.success() {
+        return Err(Error::Other(format!("{} hook fail, {}", event, status)));
+    }
+    Ok(())
+}
+
+/// Hooks running in background, reaped from the event loop.
+#[derive(Default)]
+pub struct Children(Vec<(&'static str, Child)>);
+
+impl Children {
+    pub fn spawn(&mut self, cmd: &str, event: &'static str, env: Env) {
+        debug!("run {} hook: {}", event, cmd);
+        match command(cmd, event, env).spawn() {
+            Ok(child) => self.0.push((event, child)),
+            Err(e) => warn!("run {} hook fail, {}", event, e),
+        }
+    }
+
+    // Waits for the running hooks, at shutdown.
+    pub fn wait(&mut self) {
+        for (event, mut child) in self.0.drain(..) {
+            match child.wait() {
+                Ok(status) if !status.success() => warn!("{} hook fail, {}", event, status),
+                Ok(_) => {}
+                Err(e) => warn!("wait {} hook fail, {}", event, e),
+            }
+        }
+    }
+
+    // Collects exited hooks.
+    pub fn reap(&mut self) {
+        self.0.retain_mut(|(event, child)| match child.try_wait() {
+            Ok(Some(status)) => {
+                if !status.success() {
+                    warn!("{} hook fail, {}", event, status);
+                }
+                false
+            }
+            Ok(None) => true,
+            Err(e) => {
+                warn!("wait {} hook fail, {}", event, e);
+                false
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    #[test]
+    fn test() {
+        let mut config = Config::new();
+        config.with_ip_addr("10.0.0.1/24".parse().unwrap());
+        config.routes = vec![("192.168.0.0/16".parse().unwrap(), None, None)];
+
+        let env = client_env(
+            &config,
+            "mv0",
+            "10.0.0.2".parse().unwrap(),
+            "1.2.3.4:1234".parse().unwrap(),
+        );
+        assert!(run(
+            "test \"$MINIVTUN_EVENT $MINIVTUN_IFNAME $MINIVTUN_IPV4_ADDR $MINIVTUN_ROUTES $MINIVTUN_PEER_VIP\" = \
+             \"client-connect mv0 10.0.0.1/24 192.168.0.0/16 10.0.0.2\"",
+            "client-connect",
+            env,
+        )
+        .is_ok());
+        assert!(run("exit 1", "up", Env::new()).is_err());
+    }
+}
@@ -1,6 +1,8 @@
 pub mod config;
 pub use config::Config;
 
+pub mod acl;
+
 mod client;
 pub use client::Client;
 
@@ -12,15 +14,32 @@ pub use error::Error;
 
 pub mod cryptor;
 
+pub mod dns;
+
+pub mod hook;
+
 pub mod msg;
 
+pub mod nat;
+
+pub mod netconf;
+
+pub mod shaper;
+
+mod fragment;
+mod mss;
+mod pmtu;
 mod poll;
 mod route;
 
+mod snapshot;
+
 mod socket;
 pub use socket::*;
 
 mod state;
+mod stats;
+mod trie;
 mod util;
 
 mod factory;
@@ -28,3 +47,6 @@ pub use factory::*;
 
 mod runtime;
 pub use runtime::*;
+
+mod tunnels;
+pub use tunnels::Tunnels;
@@ -1,12 +1,14 @@
 use daemonize::Daemonize;
-use ipnet::IpNet;
 use log::{debug, info, warn};
+use minivtun::netconf::{self, Netlink};
+use nix::errno::Errno;
+use nix::sys::signal::{SigSet, Signal};
 use std::fs;
 use std::io::{Read, Write};
 use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::Path;
-use std::{panic, process::Command};
+use std::panic;
+use std::path::{Path, PathBuf};
 use tun::{AbstractDevice, Device};
 mod flags;
 use minivtun::*;
@@ -28,103 +30,388 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }));
 
     let mut config = Config::new();
-    flags::parse(&mut config)?;
+    flags::parse(&mut config, None)?;
+
+    // Tunnels declared in the config file run in one process
+    let tunnels = match config.config_file {
+        Some(ref path) => config::File::load(path)?.tunnel_names(),
+        None => Vec::new(),
+    };
+    let multi = !tunnels.is_empty();
 
     // Show information only if specified
     if config.info {
-        show_info(&config)?;
+        send_control(&config, multi, "show-info")?;
         return Ok(());
     }
 
     // Trigger change server if specified
     if config.change_server {
-        trigger_change_server(&config)?;
+        send_control(&config, multi, "change-server")?;
+        return Ok(());
+    }
+
+    // Trigger config reload if specified
+    if config.reload {
+        send_control(&config, multi, "reload")?;
         return Ok(());
     }
 
+    // Handle signals, must be set before any thread is spawned
+    let exit_signal = signal_fd(&[Signal::SIGTERM, Signal::SIGINT])?;
+    let reload_signal = signal_fd(&[Signal::SIGHUP])?;
+
+    if multi {
+        run_tunnels(&config, &tunnels, exit_signal, reload_signal)
+    } else {
+        run_single(config, exit_signal, reload_signal)
+    }
+}
+
+fn run_single(
+    config: Config,
+    exit_signal: OwnedFd,
+    reload_signal: OwnedFd,
+) -> Result<(), Box<dyn std::error::Error>> {
     let config = Rc::new(config);
+    let (mut builder, tun_name) = new_builder(&config, None)?;
+
+    // Create Unix control socket
+    let (control_socket, control_path) = match bind_control(&tun_name) {
+        Ok(v) => v,
+        Err(e) => {
+            abort_tunnels(&[(config, tun_name)]);
+            return Err(e.into());
+        }
+    };
+    builder.with_control_fd(control_socket);
+    builder.with_exit_signal(exit_signal);
+    builder.with_reload_signal(reload_signal);
+
+    let result = run(config.clone(), builder, &tun_name);
+    run_down_hook(&config, &tun_name);
+
+    // Clean up the control socket on exit
+    remove_control(&control_path);
+
+    result
+}
+
+fn run_tunnels(
+    config: &Config,
+    names: &[String],
+    exit_signal: OwnedFd,
+    reload_signal: OwnedFd,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut configs = Vec::new();
+    for name in names {
+        let mut tunnel_config = Config::new();
+        flags::parse(&mut tunnel_config, Some(name))?;
+        configs.push((name, Rc::new(tunnel_config)));
+    }
+    check_resolv_confs(&configs)?;
+
+    // the tunnels set up before one fails are torn down
+    let mut tunnels = Tunnels::new();
+    let mut downs = Vec::new();
+    for (name, tunnel_config) in configs {
+        if let Err(e) = add_tunnel(&mut tunnels, &mut downs, config, name, tunnel_config) {
+            abort_tunnels(&downs);
+            return Err(e);
+        }
+    }
+
+    let (control_socket, control_path) = match bind_control(&instance_name(config)) {
+        Ok(v) => v,
+        Err(e) => {
+            abort_tunnels(&downs);
+            return Err(e.into());
+        }
+    };
+    tunnels.with_control_fd(control_socket);
+    tunnels.with_exit_signal(exit_signal);
+    tunnels.with_reload_signal(reload_signal);
+
+    do_daemonize(config);
+    let result = tunnels.run();
+    for (config, tun_name) in downs {
+        run_down_hook(&config, &tun_name);
+    }
+
+    remove_control(&control_path);
+
+    result
+}
+
+// Sets up a tunnel of the config file, added to `downs` once its interface is.
+fn add_tunnel(
+    tunnels: &mut Tunnels,
+    downs: &mut Vec<(Rc<Config>, String)>,
+    config: &Config,
+    name: &str,
+    tunnel_config: Rc<Config>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (builder, tun_name) = new_builder(&tunnel_config, Some(name))?;
+    downs.push((tunnel_config.clone(), tun_name.clone()));
+    let rt = builder.build()?;
+
+    if let Some(remote_id) = get_remote_id(&tunnel_config) {
+        info!(
+            "Tunnel {:}: client to {:}, interface: {:}.",
+            name, remote_id, tun_name
+        );
+        tunnels.with_client(name, Client::new(tunnel_config, rt)?);
+    } else {
+        check_daemonize(config.daemonize, &tunnel_config)?;
+        info!(
+            "Tunnel {:}: server on {:}, interface: {:}.",
+            name,
+            local_addr(&rt),
+            tun_name
+        );
+        tunnels.with_server(name, Server::new(tunnel_config, rt)?);
+    }
+
+    Ok(())
+}
+
+// Deconfigures the interfaces of tunnels failing before they run, and runs their
+// down hooks. The masquerade is removed when its runtime is dropped.
+fn abort_tunnels(downs: &[(Rc<Config>, String)]) {
+    for (config, tun_name) in downs {
+        let _ = deconfig_tun(config, tun_name).inspect_err(|e| warn!("{}", e));
+        run_down_hook(config, tun_name);
+    }
+}
+
+// Client tunnels accepting DNS must each write their own resolv.conf.
+fn check_resolv_confs(configs: &[(&String, Rc<Config>)]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut paths: Vec<(PathBuf, &str)> = Vec::new();
+    for (name, config) in configs {
+        if !config.accept_dns || !config.is_client() {
+            continue;
+        }
+        let path = resolv_conf(config)?;
+        if let Some((_, other)) = paths.iter().find(|(v, _)| *v == path) {
+            return Err(format!(
+                "tunnels {} and {} both accept dns to {}",
+                other,
+                name,
+                path.display()
+            )
+            .into());
+        }
+        paths.push((path, name));
+    }
+    Ok(())
+}
+
+// The resolv.conf of the namespace of the tunnel, `ip netns exec` takes that of a
+// named one from /etc/netns. One given by path has none.
+fn resolv_conf(config: &Config) -> Result<PathBuf, Error> {
+    match config.netns.as_deref() {
+        None => Ok(PathBuf::from(dns::RESOLV_CONF)),
+        Some(netns) if netns.contains('/') => Err(Error::InvalidArg(format!(
+            "accept-dns is not supported in netns {:?}, use a named one",
+            netns
+        ))),
+        Some(netns) => Ok(Path::new("/etc/netns").join(netns).join("resolv.conf")),
+    }
+}
+
+// Creates the TUN interface and a runtime builder for it.
+fn new_builder(
+    config: &Rc<Config>,
+    tunnel: Option<&str>,
+) -> Result<(RuntimeBuilder, String), Box<dyn std::error::Error>> {
     let mut builder = RuntimeBuilder::new(config.clone());
+    let resolv_conf = match config.accept_dns {
+        true => Some(resolv_conf(config)?),
+        false => None,
+    };
 
     // Create TUN interface
-    let tun = config_tun(&config)?;
+    let tun = config_tun(config)?;
     let tun_name = tun.tun_name()?;
     builder.with_tun_fd(unsafe { OwnedFd::from_raw_fd(tun.into_raw_fd()) });
+    builder.with_tun_name(tun_name.clone());
 
-    // Create Unix control socket
+    // the interface is deconfigured if the rest fails, the masquerade is removed
+    // when the builder is dropped
+    if let Err(e) = setup_builder(&mut builder, config, tunnel, &tun_name, resolv_conf) {
+        let _ = deconfig_tun(config, &tun_name).inspect_err(|e| warn!("{}", e));
+        return Err(e);
+    }
+
+    // Warn if encryption is not enabled
+    if config.cryptor.is_none() {
+        warn!("*** WARNING: Transmission will not be encrypted.");
+    }
+
+    Ok((builder, tun_name))
+}
+
+fn setup_builder(
+    builder: &mut RuntimeBuilder,
+    config: &Config,
+    tunnel: Option<&str>,
+    tun_name: &str,
+    resolv_conf: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tunnel = tunnel.map(String::from);
+    builder.with_config_loader(Box::new(move || {
+        let mut config = Config::new();
+        flags::parse(&mut config, tunnel.as_deref())?;
+        Ok(config)
+    }));
+
+    if let Some(ref out) = config.masquerade {
+        if config.is_client() {
+            warn!("masquerade is for servers, ignored");
+        } else {
+            let mut masquerade = nat::Masquerade::new(config, tun_name, out);
+            masquerade.install()?;
+            builder.with_masquerade(masquerade);
+        }
+    }
+
+    if let Some(ref cmd) = config.up {
+        hook::run(cmd, "up", hook::env(config, tun_name))?;
+    }
+
+    if let Some(path) = resolv_conf {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        builder.with_resolver(Box::new(dns::ResolvConf::new(path)));
+    }
+
+    Ok(())
+}
+
+fn run_down_hook(config: &Config, tun_name: &str) {
+    if let Some(ref cmd) = config.down {
+        let _ = hook::run(cmd, "down", hook::env(config, tun_name)).inspect_err(|e| warn!("{}", e));
+    }
+}
+
+fn bind_control(name: &str) -> std::io::Result<(UnixListener, PathBuf)> {
     let control_path = Path::new(CONTROL_PATH_BASE)
-        .join(&tun_name)
+        .join(name)
         .with_extension("sock");
     if control_path.exists() {
         fs::remove_file(&control_path)?;
     }
 
     fs::create_dir_all(CONTROL_PATH_BASE)?;
-    let control_socket = UnixListener::bind(control_path)?;
-    builder.with_control_fd(control_socket);
+    let control_socket = UnixListener::bind(&control_path)?;
+    Ok((control_socket, control_path))
+}
 
-    // Warn if encryption is not enabled
-    if config.cryptor.is_none() {
-        warn!("*** WARNING: Transmission will not be encrypted.");
+fn remove_control(control_path: &Path) {
+    let _ = fs::remove_file(control_path)
+        .inspect_err(|e| warn!("remove {} fail, {}", control_path.display(), e));
+}
+
+// Name of the control socket when running the tunnels of a config file.
+fn instance_name(config: &Config) -> String {
+    config
+        .config_file
+        .as_ref()
+        .and_then(|p| p.file_stem())
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "minivtun".to_owned())
+}
+
+fn local_addr(rt: &Runtime) -> String {
+    rt.socket()
+        .ok_or(std::io::Error::other("socket not created"))
+        .and_then(|s| s.local_addr())
+        .map(|v| v.to_string())
+        .unwrap_or_else(|_| "<NA>".to_string())
+}
+
+#[allow(unused_variables)]
+fn check_daemonize(daemonize: bool, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "holepunch")]
+    if daemonize && config.rndz().is_some() {
+        //NOTE:
+        // Daemonization is not supported in listen mode with holepunc .
+        // The server builder spawns threads before the `fork()` call for daemonization.
+        // These threads would be lost in the child process, as `fork()` only preserves
+        // the calling thread, rendering the server non-functional.
+        //
+        // To support this, server creation would need to happen *after* daemonizing.
+        // However, this would prevent reporting startup errors (e.g., socket bind
+        // failure) to the user, as the process would already be detached from the terminal.
+
+        return Err("Daemonizing with holepunch enabled is not supported.".into());
     }
 
-    let rt = builder.build()?;
+    Ok(())
+}
+
+// Runs the client or server of the tunnel, the interface is deconfigured if it
+// fails to start.
+fn run(
+    config: Rc<Config>,
+    builder: RuntimeBuilder,
+    tun_name: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let deconfig = || {
+        let _ = deconfig_tun(&config, tun_name).inspect_err(|e| warn!("{}", e));
+    };
+    let rt = builder.build().inspect_err(|_| deconfig())?;
 
     // Run client or server based on configuration
     if let Some(remote_id) = get_remote_id(&config) {
         info!(
             "Mini virtual tunneling client to {:}, interface: {:}.",
-            remote_id, &tun_name
+            remote_id, tun_name
         );
 
         do_daemonize(&config);
-        Client::new(config, rt)?.run()
+        Client::new(config.clone(), rt)
+            .inspect_err(|_| deconfig())?
+            .run()
     } else {
-        #[cfg(feature = "holepunch")]
-        if config.daemonize && config.rndz().is_some() {
-            //NOTE:
-            // Daemonization is not supported in listen mode with holepunc .
-            // The server builder spawns threads before the `fork()` call for daemonization.
-            // These threads would be lost in the child process, as `fork()` only preserves
-            // the calling thread, rendering the server non-functional.
-            //
-            // To support this, server creation would need to happen *after* daemonizing.
-            // However, this would prevent reporting startup errors (e.g., socket bind
-            // failure) to the user, as the process would already be detached from the terminal.
-
-            return Err("Daemonizing with holepunch enabled is not supported.".into());
-        }
+        check_daemonize(config.daemonize, &config).inspect_err(|_| deconfig())?;
 
         info!(
             "Mini virtual tunneling server on {:}, interface: {:}.",
-            rt.socket()
-                .ok_or(std::io::Error::other("socket not created"))
-                .and_then(|s| s.local_addr())
-                .map(|v| v.to_string())
-                .unwrap_or_else(|_| "<NA>".to_string()),
-            &tun_name
+            local_addr(&rt),
+            tun_name
         );
 
         do_daemonize(&config);
-        Server::new(config, rt)?.run()
+        Server::new(config.clone(), rt)
+            .inspect_err(|_| deconfig())?
+            .run()
     }
 }
 
-fn get_control_stream(config: &Config) -> std::io::Result<UnixStream> {
+fn get_control_stream(config: &Config, multi: bool) -> std::io::Result<UnixStream> {
+    let name = if multi {
+        instance_name(config)
+    } else {
+        config
+            .ifname
+            .as_ref()
+            .expect("interface name not set")
+            .replace("%d", "0")
+    };
     let control_path = Path::new(CONTROL_PATH_BASE)
-        .join(
-            config
-                .ifname
-                .as_ref()
-                .expect("interface name not set")
-                .replace("%d", "0"),
-        )
+        .join(name)
         .with_extension("sock");
     UnixStream::connect(control_path)
 }
 
-fn show_info(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
-    if let Ok(mut ctrl) = get_control_stream(config) {
-        ctrl.write_all(b"show-info\n")?;
+fn send_control(config: &Config, multi: bool, cmd: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if let Ok(mut ctrl) = get_control_stream(config, multi) {
+        match config.tunnel {
+            Some(ref tunnel) => ctrl.write_all(format!("{} {}\n", tunnel, cmd).as_bytes())?,
+            None => ctrl.write_all(format!("{}\n", cmd).as_bytes())?,
+        }
         let mut response = String::new();
         ctrl.read_to_string(&mut response)?;
         println!("{}", response);
@@ -132,80 +419,33 @@ fn show_info(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn trigger_change_server(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
-    if let Ok(mut ctrl) = get_control_stream(config) {
-        ctrl.write_all(b"change-server\n")?;
-        let mut response = String::new();
-        ctrl.read_to_string(&mut response)?;
-        println!("{}", response);
+// Blocks the signals and returns a signalfd to receive them.
+fn signal_fd(signals: &[Signal]) -> nix::Result<OwnedFd> {
+    let mut mask = SigSet::empty();
+    for s in signals {
+        mask.add(*s);
     }
-    Ok(())
+    mask.thread_block()?;
+
+    let fd = unsafe { libc::signalfd(-1, mask.as_ref(), libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+    Errno::result(fd).map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
 }
 
+// The privileges are kept to reconfigure the interface on reload and clean it up
+// on shutdown.
 fn do_daemonize(config: &Config) {
     if config.daemonize {
-        Daemonize::new()
-            .user("nobody")
-            .start()
-            .expect("start daemonize fail");
+        Daemonize::new().start().expect("start daemonize fail");
     }
 }
 
-fn add_addr(addr: IpNet, dev: &str) -> Result<(), Error> {
-    let mut c = Command::new("ip");
-    if let IpNet::V6(_) = addr {
-        c.arg("-6");
-    }
-
-    if c.arg("addr")
-        .arg("add")
-        .arg(addr.to_string())
-        .arg("dev")
-        .arg(dev)
-        .status()
-        .is_ok_and(|c| c.success())
-    {
-        return Ok(());
-    }
-
-    Err(Error::AddAddrFail)
-}
-
-fn add_route(
-    addr: &IpNet,
-    dev: &str,
-    table: &Option<String>,
-    metric: &Option<String>,
-) -> Result<(), Error> {
-    let mut c = Command::new("ip");
-    if let IpNet::V6(_) = addr {
-        c.arg("-6");
-    }
-
-    c.arg("route")
-        .arg("add")
-        .arg(addr.to_string())
-        .arg("dev")
-        .arg(dev);
-
-    if let Some(table) = table {
-        c.arg("table");
-        c.arg(table);
-    }
-
-    if let Some(metric) = metric {
-        c.arg("metric");
-        c.arg(metric);
-    }
-
-    if c.status().is_ok_and(|c| c.success()) {
-        return Ok(());
-    }
-
-    Err(Error::AddRouteFail)
+// The interface is created and configured in the network namespace of the config,
+// the socket is created later and stays in the current one.
+fn config_tun(config: &Config) -> Result<Device, Box<dyn std::error::Error>> {
+    netconf::in_netns(config.netns.as_deref(), || create_tun(config))
 }
 
-fn config_tun(config: &Config) -> Result<Device, Box<dyn std::error::Error>> {
+fn create_tun(config: &Config) -> Result<Device, Box<dyn std::error::Error>> {
     let mut tun_config = tun::configure();
     if let Some(ref name) = config.ifname {
         tun_config.tun_name(name);
@@ -213,25 +453,35 @@ fn config_tun(config: &Config) -> Result<Device, Box<dyn std::error::Error>> {
 
     tun_config.mtu(config.mtu);
 
+    if config.tap {
+        tun_config.layer(tun::Layer::L2);
+    }
+
     tun_config.up();
 
     let tun: Device = tun::create(&tun_config)?;
     let tun_name = tun.tun_name()?;
     tun.set_nonblock()?;
 
+    let nl = Netlink::new()?;
     if let Some(addr4) = config.loc_tun_in {
         debug!("add address {}", addr4);
-        add_addr(addr4.into(), &tun_name)?;
+        nl.add_addr(addr4.into(), &tun_name)?;
     };
 
     if let Some(addr6) = config.loc_tun_in6 {
         debug!("add address {}", addr6);
-        add_addr(addr6.into(), &tun_name)?;
+        nl.add_addr(addr6.into(), &tun_name)?;
     };
 
-    for (net, _) in &config.routes {
-        debug!("add route {}", net);
-        add_route(net, &tun_name, &config.table, &config.metric)?;
+    for route in config.tun_routes(&tun_name)? {
+        debug!("add route {}", route);
+        nl.add_route(&route)?;
+    }
+
+    for rule in config.tun_rules()? {
+        debug!("add rule {}", rule);
+        nl.add_rule(&rule)?;
     }
 
     Ok(tun)
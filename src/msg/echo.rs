@@ -3,11 +3,78 @@ use super::Encryptor;
 use crate::error::{Error, Result};
 use crate::msg::builder::{Builder as Build, Finalizer};
 use byteorder::{BigEndian, ByteOrder};
+use ipnet::IpNet;
+use num_enum::TryFromPrimitive;
 use packet::{buffer::Dynamic, Buffer};
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 const PACKET_SIZE: usize = 24;
 
+#[derive(PartialEq, Eq, Debug, TryFromPrimitive)]
+#[repr(u8)]
+enum ExtType {
+    // Ends the extensions, also matches the zero padding of encryption
+    End,
+    Dns,
+    DnsSearch,
+    Route,
+    ClientId,
+}
+
+/// Extensions appended after the fixed fields as type, length and value. Old versions
+/// only read the fixed fields and ignore them.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Ext {
+    Dns(IpAddr),
+    DnsSearch(String),
+    Route(IpNet),
+    // Random identity of a client process, kept when it roams to another address
+    ClientId(u64),
+}
+
+impl Ext {
+    fn encode(&self) -> (ExtType, Vec<u8>) {
+        match self {
+            Ext::Dns(IpAddr::V4(addr)) => (ExtType::Dns, addr.octets().to_vec()),
+            Ext::Dns(IpAddr::V6(addr)) => (ExtType::Dns, addr.octets().to_vec()),
+            Ext::DnsSearch(domain) => (ExtType::DnsSearch, domain.as_bytes().to_vec()),
+            // prefix length followed by the network address
+            Ext::Route(net) => {
+                let mut v = vec![net.prefix_len()];
+                match net.network() {
+                    IpAddr::V4(addr) => v.extend_from_slice(&addr.octets()),
+                    IpAddr::V6(addr) => v.extend_from_slice(&addr.octets()),
+                }
+                (ExtType::Route, v)
+            }
+            Ext::ClientId(id) => (ExtType::ClientId, id.to_be_bytes().to_vec()),
+        }
+    }
+
+    fn decode(t: ExtType, v: &[u8]) -> Option<Self> {
+        match t {
+            ExtType::End => None,
+            ExtType::Dns => ip_addr(v).map(Ext::Dns),
+            ExtType::DnsSearch => std::str::from_utf8(v)
+                .ok()
+                .map(|s| Ext::DnsSearch(s.to_owned())),
+            ExtType::Route => {
+                let (len, addr) = v.split_first()?;
+                IpNet::new(ip_addr(addr)?, *len).ok().map(Ext::Route)
+            }
+            ExtType::ClientId => Some(Ext::ClientId(u64::from_be_bytes(v.try_into().ok()?))),
+        }
+    }
+}
+
+fn ip_addr(v: &[u8]) -> Option<IpAddr> {
+    match v.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(v).ok()?)),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(v).ok()?)),
+        _ => None,
+    }
+}
+
 pub struct Builder<F: Finalizer<B>, B: Buffer> {
     buffer: B,
     finalizer: F,
@@ -47,6 +114,35 @@ impl<F: Finalizer<B>, B: Buffer> Builder<F, B> {
         self.buffer.data_mut()[4..20].copy_from_slice(&addr.octets());
         Ok(self)
     }
+
+    pub fn ext(mut self, ext: &Ext) -> Result<Self> {
+        let (t, v) = ext.encode();
+        if v.len() > u8::MAX as usize {
+            Err(Error::InvalidArg(format!(
+                "echo extension too long, {:?}",
+                ext
+            )))?
+        }
+
+        let i = self.buffer.length();
+        self.buffer.more(2 + v.len())?;
+        let data = &mut self.buffer.data_mut()[i..];
+        data[0] = t as u8;
+        data[1] = v.len() as u8;
+        data[2..].copy_from_slice(&v);
+        Ok(self)
+    }
+
+    /// Pads the packet with zeros to `len` bytes, read as the end of the
+    /// extensions. A path MTU probe.
+    pub fn pad(mut self, len: usize) -> Result<Self> {
+        let i = self.buffer.length();
+        if len > i {
+            self.buffer.more(len - i)?;
+            self.buffer.data_mut()[i..].fill(0);
+        }
+        Ok(self)
+    }
 }
 
 pub struct Packet<B> {
@@ -72,6 +168,27 @@ impl<B: AsRef<[u8]>> Packet<B> {
             BigEndian::read_u128(&buf[4..20]).into(),
         ))
     }
+
+    // Extensions up to the end or a truncated one, unknown ones are skipped.
+    pub fn exts(&self) -> Vec<Ext> {
+        let mut exts = Vec::new();
+        let mut buf = &self.buffer.as_ref()[PACKET_SIZE..];
+        while buf.len() >= 2 && buf[0] != ExtType::End as u8 {
+            let len = buf[1] as usize;
+            if buf.len() < 2 + len {
+                break;
+            }
+
+            if let Some(ext) = ExtType::try_from(buf[0])
+                .ok()
+                .and_then(|t| Ext::decode(t, &buf[2..2 + len]))
+            {
+                exts.push(ext);
+            }
+            buf = &buf[2 + len..];
+        }
+        exts
+    }
 }
 
 #[cfg(test)]
@@ -97,5 +214,35 @@ mod tests {
         let p = Packet::new(buf).unwrap();
         assert_eq!(p.id().unwrap(), 1);
         assert_eq!(p.ip_addr().unwrap(), (addr4, addr6));
+        assert!(p.exts().is_empty());
+
+        let exts = [
+            Ext::Dns("10.0.0.1".parse().unwrap()),
+            Ext::DnsSearch("corp.example.com".into()),
+            Ext::Route("192.168.0.0/16".parse().unwrap()),
+            Ext::Route("fd00::/8".parse().unwrap()),
+            Ext::ClientId(0x0102030405060708),
+        ];
+        let mut buf = exts
+            .iter()
+            .try_fold(Builder::default(), |b, ext| b.ext(ext))
+            .unwrap()
+            .build()
+            .unwrap();
+        // unknown extension and zero padding
+        buf.extend_from_slice(&[0xff, 1, 0, 0, 0]);
+
+        assert_eq!(Packet::new(buf).unwrap().exts(), exts);
+
+        let ext = Ext::ClientId(1);
+        let buf = Builder::default()
+            .ext(&ext)
+            .unwrap()
+            .pad(100)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(buf.len(), 100);
+        assert_eq!(Packet::new(buf).unwrap().exts(), [ext]);
     }
 }
@@ -0,0 +1,135 @@
+use super::encrypt::NO_ENCRYPT;
+use super::Encryptor;
+use crate::error::{Error, Result};
+use crate::msg::builder::{Builder as Build, Finalizer};
+use byteorder::{BigEndian, ByteOrder};
+use packet::{buffer::Dynamic, Buffer};
+
+/// Id, index, count and payload length of a piece of an ip data message.
+pub const HEADER_SIZE: usize = 6;
+
+pub struct Builder<F: Finalizer<B>, B: Buffer> {
+    buffer: B,
+    payload: bool,
+    finalizer: F,
+}
+
+impl<'a> Default for Builder<Encryptor<'a>, Dynamic> {
+    fn default() -> Self {
+        Builder::with(Dynamic::default(), NO_ENCRYPT).unwrap()
+    }
+}
+
+impl<F: Finalizer<B>, B: Buffer> Build for Builder<F, B> {
+    fn build(self) -> Result<Vec<u8>> {
+        self.finalizer.finalize(self.buffer)
+    }
+}
+
+impl<F: Finalizer<B>, B: Buffer> Builder<F, B> {
+    pub fn with(mut buf: B, finalizer: F) -> Result<Builder<F, B>> {
+        buf.next(HEADER_SIZE)?;
+        Ok(Builder {
+            buffer: buf,
+            payload: false,
+            finalizer,
+        })
+    }
+
+    pub fn id(mut self, id: u16) -> Result<Self> {
+        BigEndian::write_u16(&mut self.buffer.data_mut()[0..], id);
+        Ok(self)
+    }
+
+    pub fn index(mut self, index: u8, count: u8) -> Result<Self> {
+        if index >= count {
+            Err(Error::InvalidPacket)?
+        }
+
+        self.buffer.data_mut()[2] = index;
+        self.buffer.data_mut()[3] = count;
+        Ok(self)
+    }
+
+    pub fn payload(mut self, value: &[u8]) -> Result<Self> {
+        if self.payload {
+            Err(Error::InvalidPacket)?
+        }
+
+        self.payload = true;
+
+        let i = self.buffer.length();
+        self.buffer.more(value.len())?;
+        self.buffer.data_mut()[i..].copy_from_slice(value);
+
+        BigEndian::write_u16(&mut self.buffer.data_mut()[4..], value.len() as u16);
+
+        Ok(self)
+    }
+}
+
+pub struct Packet<B> {
+    buffer: B,
+}
+
+impl<B: AsRef<[u8]>> Packet<B> {
+    pub fn new(buf: B) -> Result<Self> {
+        if buf.as_ref().len() < HEADER_SIZE {
+            Err(Error::InvalidPacket)?
+        }
+
+        Ok(Self { buffer: buf })
+    }
+
+    pub fn id(&self) -> Result<u16> {
+        Ok(BigEndian::read_u16(&self.buffer.as_ref()[0..]))
+    }
+
+    pub fn index(&self) -> Result<u8> {
+        Ok(self.buffer.as_ref()[2])
+    }
+
+    pub fn count(&self) -> Result<u8> {
+        Ok(self.buffer.as_ref()[3])
+    }
+
+    pub fn payload_length(&self) -> Result<u16> {
+        Ok(BigEndian::read_u16(&self.buffer.as_ref()[4..]))
+    }
+
+    pub fn payload(&self) -> Result<&[u8]> {
+        let valid_len = HEADER_SIZE + self.payload_length()? as usize;
+        if self.buffer.as_ref().len() < valid_len {
+            Err(Error::InvalidPacket)?
+        }
+
+        Ok(&self.buffer.as_ref()[HEADER_SIZE..valid_len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    #[test]
+    fn test() {
+        let buf = Builder::default()
+            .id(7)
+            .unwrap()
+            .index(1, 3)
+            .unwrap()
+            .payload(&[1; 5])
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(buf.len(), 6 + 5);
+
+        let p = Packet::new(buf).unwrap();
+        assert_eq!(p.id().unwrap(), 7);
+        assert_eq!((p.index().unwrap(), p.count().unwrap()), (1, 3));
+        assert_eq!(p.payload().unwrap(), &[1; 5]);
+
+        assert!(Builder::default().index(3, 3).is_err());
+    }
+}
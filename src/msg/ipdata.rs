@@ -7,14 +7,16 @@ use num_enum::TryFromPrimitive;
 use packet::{buffer::Dynamic, Buffer};
 use std::convert::TryFrom;
 
-#[derive(PartialEq, Eq, Debug, TryFromPrimitive)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, TryFromPrimitive)]
 #[repr(u16)]
 pub enum Kind {
     V4 = 0x0800,
     V6 = 0x86dd,
+    // Ethernet frame, carried in TAP mode
+    Ether = 0x6558,
 }
 
-const HEADER_SIZE: usize = 4;
+pub const HEADER_SIZE: usize = 4;
 
 pub struct Builder<F: Finalizer<B>, B: Buffer> {
     buffer: B,
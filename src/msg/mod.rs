@@ -1,12 +1,19 @@
 mod builder;
 mod echo;
 mod encrypt;
+mod fragment;
 mod ipdata;
 #[allow(clippy::module_inception)]
 mod msg;
 
 pub use builder::{Builder, Finalizer};
-pub use echo::{Builder as EchoBuilder, Packet as EchoPacket};
+pub use echo::{Builder as EchoBuilder, Ext as EchoExt, Packet as EchoPacket};
 pub use encrypt::Encryptor;
-pub use ipdata::{Builder as IpDataBuilder, Kind as IpDataKind, Packet as IpDataPacket};
-pub use msg::{Builder as MsgBuilder, Op, Packet as MsgPacket};
+pub use fragment::{
+    Builder as FragmentBuilder, Packet as FragmentPacket, HEADER_SIZE as FRAGMENT_HEADER_SIZE,
+};
+pub use ipdata::{
+    Builder as IpDataBuilder, Kind as IpDataKind, Packet as IpDataPacket,
+    HEADER_SIZE as IP_DATA_HEADER_SIZE,
+};
+pub use msg::{Builder as MsgBuilder, Op, Packet as MsgPacket, HEADER_SIZE as MSG_HEADER_SIZE};
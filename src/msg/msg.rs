@@ -15,9 +15,11 @@ pub enum Op {
     IpData,
     Disconnect,
     EchoAck,
+    // A piece of an ip data message too large for a datagram
+    Fragment,
 }
 
-const HEADER_SIZE: usize = 20;
+pub const HEADER_SIZE: usize = 20;
 
 pub struct Builder<'a, B: Buffer = Dynamic> {
     buffer: B,
@@ -104,6 +106,11 @@ impl<'a, B: Buffer> Builder<'a, B> {
         let new_self = self.op(Op::IpData)?;
         crate::msg::ipdata::Builder::with(new_self.buffer, new_self.finalizer)
     }
+
+    pub fn fragment(self) -> Result<crate::msg::fragment::Builder<Encryptor<'a>, B>> {
+        let new_self = self.op(Op::Fragment)?;
+        crate::msg::fragment::Builder::with(new_self.buffer, new_self.finalizer)
+    }
 }
 
 pub struct Packet<B> {
@@ -113,7 +120,7 @@ pub struct Packet<B> {
 impl<B: AsRef<[u8]>> Packet<B> {
     pub fn new(buf: B) -> Result<Self> {
         if buf.as_ref().len() < HEADER_SIZE {
-            Err(Error::InvalidPacket)?
+            Err(Error::ShortPacket)?
         }
 
         Ok(Self { buffer: buf })
@@ -124,15 +131,18 @@ impl<B: AsRef<[u8]>> Packet<B> {
         cryptor: Option<&dyn Cryptor>,
     ) -> Result<Packet<&'a [u8]>> {
         if buffer.len() < HEADER_SIZE {
-            Err(Error::InvalidPacket)?
+            Err(Error::ShortPacket)?
         }
 
         let out = match cryptor {
             None => buffer,
             Some(cryptor) => {
                 let out = cryptor.decrypt(buffer)?;
+                if out.len() < HEADER_SIZE {
+                    Err(Error::ShortPacket)?
+                }
                 if out[4..20] != *cryptor.auth_key() {
-                    Err(Error::InvalidPacket)?
+                    Err(Error::AuthFail)?
                 };
 
                 out
@@ -219,5 +229,20 @@ mod tests {
             .unwrap();
 
         assert_eq!(buf.len(), 20 + 4 + 6);
+
+        let mut buf = Builder::default()
+            .fragment()
+            .unwrap()
+            .id(1)
+            .unwrap()
+            .index(0, 2)
+            .unwrap()
+            .payload(&[0; 6])
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let p = Packet::<&[u8]>::with_cryptor(&mut buf, None).unwrap();
+        assert_eq!(p.op().unwrap(), Op::Fragment);
     }
 }
@@ -0,0 +1,142 @@
+//! Clamping of the MSS option of TCP SYN segments, so that the segments of a
+//! connection through the tunnel fit its MTU without path MTU discovery.
+
+const TCP: u8 = 6;
+const SYN: u8 = 0x02;
+const MSS_OPTION: u8 = 2;
+
+/// A copy of the IP packet with the MSS option lowered to `mss` and the TCP
+/// checksum fixed up, None unless it is a SYN or SYN-ACK announcing more.
+pub fn clamp(pkt: &[u8], mss: u16) -> Option<Vec<u8>> {
+    let (offset, end, addrs) = match pkt.first()? >> 4 {
+        4 if pkt.len() >= 20 => {
+            let ihl = (pkt[0] & 0x0f) as usize * 4;
+            let fragment = u16::from_be_bytes([pkt[6], pkt[7]]) & 0x1fff != 0;
+            if pkt[9] != TCP || fragment || ihl < 20 {
+                return None;
+            }
+            (
+                ihl,
+                u16::from_be_bytes([pkt[2], pkt[3]]) as usize,
+                &pkt[12..20],
+            )
+        }
+        // a TCP header right after the fixed one, extension headers are left alone
+        6 if pkt.len() >= 40 => {
+            if pkt[6] != TCP {
+                return None;
+            }
+            (
+                40,
+                40 + u16::from_be_bytes([pkt[4], pkt[5]]) as usize,
+                &pkt[8..40],
+            )
+        }
+        _ => return None,
+    };
+
+    let tcp = pkt.get(offset..end)?;
+    if tcp.len() < 20 || tcp[13] & SYN == 0 {
+        return None;
+    }
+    let options = tcp.get(20..(tcp[12] >> 4) as usize * 4)?;
+    let at = offset + 20 + mss_option(options)?;
+    if u16::from_be_bytes([pkt[at], pkt[at + 1]]) <= mss {
+        return None;
+    }
+
+    let mut pkt = pkt.to_vec();
+    pkt[at..at + 2].copy_from_slice(&mss.to_be_bytes());
+    pkt[offset + 16..offset + 18].fill(0);
+    let pseudo = sum(addrs, TCP as u32 + (end - offset) as u32);
+    let checksum = !fold(sum(&pkt[offset..end], pseudo));
+    pkt[offset + 16..offset + 18].copy_from_slice(&checksum.to_be_bytes());
+    Some(pkt)
+}
+
+// Offset of the MSS value in the TCP options.
+fn mss_option(options: &[u8]) -> Option<usize> {
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            // end of the options
+            0 => return None,
+            // no operation
+            1 => i += 1,
+            kind => {
+                let len = *options.get(i + 1)? as usize;
+                if len < 2 || i + len > options.len() {
+                    return None;
+                }
+                if kind == MSS_OPTION && len == 4 {
+                    return Some(i + 2);
+                }
+                i += len;
+            }
+        }
+    }
+    None
+}
+
+// Adds the big endian 16 bits words of `data` to `sum`, an odd byte is padded.
+fn sum(data: &[u8], sum: u32) -> u32 {
+    data.chunks(2).fold(sum, |sum, v| {
+        sum + u16::from_be_bytes([v[0], v.get(1).copied().unwrap_or(0)]) as u32
+    })
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    // A SYN from 10.0.0.2:1234 to 192.168.1.1:80 with NOP, NOP and MSS options.
+    fn syn(mss: u16) -> Vec<u8> {
+        let mut pkt = vec![0x45, 0, 0, 48, 0, 0, 0x40, 0, 64, TCP, 0, 0];
+        pkt.extend_from_slice(&[10, 0, 0, 2, 192, 168, 1, 1]);
+        pkt.extend_from_slice(&[0x04, 0xd2, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0]);
+        pkt.extend_from_slice(&[0x70, SYN, 0xff, 0xff, 0, 0, 0, 0]);
+        pkt.extend_from_slice(&[1, 1, MSS_OPTION, 4]);
+        pkt.extend_from_slice(&mss.to_be_bytes());
+        pkt.extend_from_slice(&[0, 0]);
+        pkt
+    }
+
+    fn is_valid(pkt: &[u8]) -> bool {
+        let pseudo = sum(&pkt[12..20], TCP as u32 + (pkt.len() - 20) as u32);
+        fold(sum(&pkt[20..], pseudo)) == 0xffff
+    }
+
+    #[test]
+    fn test() {
+        let mut pkt = syn(1460);
+        assert_eq!(clamp(&pkt, 1460), None);
+        let clamped = clamp(&pkt, 1360).unwrap();
+        assert_eq!(&clamped[44..46], &1360u16.to_be_bytes());
+        assert!(is_valid(&clamped));
+        assert_eq!(clamp(&clamped, 1360), None);
+
+        // not a SYN
+        pkt[33] = 0x10;
+        assert_eq!(clamp(&pkt, 1360), None);
+        // no MSS option
+        let mut pkt = syn(1460);
+        pkt[42] = 0;
+        assert_eq!(clamp(&pkt, 1360), None);
+        assert_eq!(clamp(&pkt[..30], 1360), None);
+
+        let mut pkt = vec![0x60, 0, 0, 0, 0, 28, TCP, 64];
+        pkt.extend_from_slice(&[0xfd; 32]);
+        pkt.extend_from_slice(&syn(1440)[20..]);
+        let clamped = clamp(&pkt, 1220).unwrap();
+        assert_eq!(&clamped[64..66], &1220u16.to_be_bytes());
+        let pseudo = sum(&clamped[8..40], TCP as u32 + 28);
+        assert_eq!(fold(sum(&clamped[40..], pseudo)), 0xffff);
+    }
+}
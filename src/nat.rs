@@ -0,0 +1,192 @@
+//! Masquerade of the tunnel subnets behind an egress interface, set up with nftables.
+
+use crate::error::Error;
+use crate::netconf;
+use crate::Config;
+use ipnet::IpNet;
+use log::{debug, info, warn};
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const IPV4_FORWARD: &str = "/proc/sys/net/ipv4/ip_forward";
+const IPV6_FORWARD: &str = "/proc/sys/net/ipv6/conf/all/forwarding";
+
+/// An nftables table forwarding and masquerading the traffic from interface `dev`
+/// out of interface `out`. What `install` changed is recorded, and undone by
+/// `remove` or on drop.
+pub struct Masquerade {
+    netns: Option<String>,
+    table: String,
+    subnets: Vec<IpNet>,
+    dev: String,
+    out: String,
+    installed: bool,
+    // forwarding sysctls enabled by `install`, with the original values
+    sysctls: Vec<(&'static str, String)>,
+}
+
+impl Masquerade {
+    /// Masquerades the subnets of the interface addresses and the attached routes.
+    pub fn new(config: &Config, dev: &str, out: &str) -> Self {
+        let mut subnets: Vec<IpNet> = config
+            .loc_tun_in
+            .map(|v| v.trunc().into())
+            .into_iter()
+            .chain(config.loc_tun_in6.map(|v| v.trunc().into()))
+            .chain(config.routes.iter().map(|(net, ..)| *net))
+            .collect();
+        subnets.dedup();
+
+        Self {
+            netns: config.netns.clone(),
+            table: format!("minivtun-{}", dev),
+            subnets,
+            dev: dev.to_owned(),
+            out: out.to_owned(),
+            installed: false,
+            sysctls: Vec::new(),
+        }
+    }
+
+    fn ruleset(&self) -> String {
+        let mut s = format!("table inet {} {{\n", self.table);
+        s += "\tchain forward {\n";
+        s += "\t\ttype filter hook forward priority filter; policy accept;\n";
+        s += &format!("\t\tiifname {:?} oifname {:?} accept\n", self.dev, self.out);
+        s += &format!(
+            "\t\tiifname {:?} oifname {:?} ct state established,related accept\n",
+            self.out, self.dev
+        );
+        s += "\t}\n";
+        s += "\tchain postrouting {\n";
+        s += "\t\ttype nat hook postrouting priority srcnat; policy accept;\n";
+        for net in &self.subnets {
+            let family = match net {
+                IpNet::V4(_) => "ip",
+                IpNet::V6(_) => "ip6",
+            };
+            s += &format!(
+                "\t\toifname {:?} {} saddr {} masquerade\n",
+                self.out, family, net
+            );
+        }
+        s += "\t}\n}\n";
+        s
+    }
+
+    /// Enables forwarding and loads the table, a table left by a previous run is replaced.
+    pub fn install(&mut self) -> Result<(), Error> {
+        netconf::in_netns(self.netns.clone().as_deref(), || {
+            if self.subnets.iter().any(|v| matches!(v, IpNet::V4(_))) {
+                self.enable_forward(IPV4_FORWARD)?;
+            }
+            if self.subnets.iter().any(|v| matches!(v, IpNet::V6(_))) {
+                self.enable_forward(IPV6_FORWARD)?;
+            }
+
+            // creating the table first makes the delete succeed if there is none
+            nft(&format!(
+                "table inet {0}\ndelete table inet {0}\n{1}",
+                self.table,
+                self.ruleset()
+            ))?;
+            self.installed = true;
+            info!("masquerade {:?} out of {}", self.subnets, self.out);
+            Ok(())
+        })
+    }
+
+    /// Deletes the table and restores the forwarding sysctls, each is attempted even
+    /// if another fails, and what failed is tried again on the next call.
+    pub fn remove(&mut self) -> Result<(), Error> {
+        netconf::in_netns(self.netns.clone().as_deref(), || {
+            let mut result = Ok(());
+            if self.installed {
+                result = nft(&format!("delete table inet {}\n", self.table));
+                if result.is_ok() {
+                    self.installed = false;
+                    info!("remove masquerade out of {}", self.out);
+                }
+            }
+
+            let mut failed = Vec::new();
+            for (path, orig) in self.sysctls.drain(..) {
+                debug!("restore {} to {}", path, orig);
+                if let Err(e) = fs::write(path, &orig) {
+                    result = result.and(Err(e.into()));
+                    failed.push((path, orig));
+                }
+            }
+            self.sysctls = failed;
+            result
+        })
+    }
+
+    fn enable_forward(&mut self, path: &'static str) -> Result<(), Error> {
+        let orig = fs::read_to_string(path)?;
+        if orig.trim() != "1" {
+            debug!("enable {}", path);
+            fs::write(path, "1")?;
+            self.sysctls.push((path, orig));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Masquerade {
+    fn drop(&mut self) {
+        let _ = self.remove().inspect_err(|e| warn!("{}", e));
+    }
+}
+
+// Runs an nft script atomically.
+fn nft(script: &str) -> Result<(), Error> {
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(script.as_bytes())?;
+    }
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(Error::Other(format!(
+            "nft fail, {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    #[test]
+    fn test() {
+        let mut config = Config::new();
+        config.with_ip_addr("10.0.0.1/24".parse().unwrap());
+        config.with_ip_addr("fd00::1/64".parse().unwrap());
+
+        let nat = Masquerade::new(&config, "mv0", "eth0");
+        assert_eq!(nat.table, "minivtun-mv0");
+        assert_eq!(
+            nat.ruleset(),
+            "table inet minivtun-mv0 {\n\
+             \tchain forward {\n\
+             \t\ttype filter hook forward priority filter; policy accept;\n\
+             \t\tiifname \"mv0\" oifname \"eth0\" accept\n\
+             \t\tiifname \"eth0\" oifname \"mv0\" ct state established,related accept\n\
+             \t}\n\
+             \tchain postrouting {\n\
+             \t\ttype nat hook postrouting priority srcnat; policy accept;\n\
+             \t\toifname \"eth0\" ip saddr 10.0.0.0/24 masquerade\n\
+             \t\toifname \"eth0\" ip6 saddr fd00::/64 masquerade\n\
+             \t}\n\
+             }\n"
+        );
+    }
+}
@@ -0,0 +1,673 @@
+//! Network configuration over rtnetlink, links, addresses, routes and policy rules.
+
+use crate::error::Error;
+use ipnet::IpNet;
+use nix::sched::{setns, CloneFlags};
+use std::cell::Cell;
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+// From linux/netlink.h, linux/rtnetlink.h, linux/if_link.h, linux/if_addr.h and
+// linux/fib_rules.h
+const NLMSG_HDRLEN: usize = 16;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_DUMP: u16 = 0x300;
+const NLM_F_EXCL: u16 = 0x200;
+const NLM_F_CREATE: u16 = 0x400;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_NEWADDR: u16 = 20;
+const RTM_DELADDR: u16 = 21;
+const RTM_NEWROUTE: u16 = 24;
+const RTM_DELROUTE: u16 = 25;
+const RTM_GETROUTE: u16 = 26;
+const RTM_NEWRULE: u16 = 32;
+const RTM_DELRULE: u16 = 33;
+
+const IFLA_MTU: u16 = 4;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+
+const RTA_DST: u16 = 1;
+const RTA_OIF: u16 = 4;
+const RTA_GATEWAY: u16 = 5;
+const RTA_PRIORITY: u16 = 6;
+const RTA_TABLE: u16 = 15;
+
+const RTPROT_BOOT: u8 = 3;
+const RT_SCOPE_UNIVERSE: u8 = 0;
+const RT_SCOPE_LINK: u8 = 253;
+const RT_SCOPE_NOWHERE: u8 = 255;
+const RTN_UNICAST: u8 = 1;
+
+const FRA_PRIORITY: u16 = 6;
+const FRA_FWMARK: u16 = 10;
+const FRA_SUPPRESS_PREFIXLEN: u16 = 14;
+const FRA_TABLE: u16 = 15;
+const FR_ACT_TO_TBL: u8 = 1;
+const FIB_RULE_INVERT: u32 = 0x2;
+
+pub const RT_TABLE_MAIN: u32 = 254;
+
+/// A route, displayed in the syntax of `ip route`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub dst: IpNet,
+    pub gateway: Option<IpAddr>,
+    pub dev: Option<String>,
+    pub table: Option<u32>,
+    pub metric: Option<u32>,
+}
+
+impl Route {
+    /// Route to `dst` on `dev`, `table` may be a name of /etc/iproute2/rt_tables.
+    pub fn new(
+        dst: IpNet,
+        dev: &str,
+        table: &Option<String>,
+        metric: &Option<String>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            dst,
+            gateway: None,
+            dev: Some(dev.to_owned()),
+            table: table.as_deref().map(table_id).transpose()?,
+            metric: metric
+                .as_deref()
+                .map(|m| {
+                    m.parse()
+                        .map_err(|_| Error::InvalidArg(format!("invalid metric {:?}", m)))
+                })
+                .transpose()?,
+        })
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.dst)?;
+        if let Some(gw) = self.gateway {
+            write!(f, " via {}", gw)?;
+        }
+        if let Some(ref dev) = self.dev {
+            write!(f, " dev {}", dev)?;
+        }
+        if let Some(table) = self.table {
+            write!(f, " table {}", table)?;
+        }
+        if let Some(metric) = self.metric {
+            write!(f, " metric {}", metric)?;
+        }
+        Ok(())
+    }
+}
+
+/// A policy routing rule, displayed in the syntax of `ip rule`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub ipv6: bool,
+    pub priority: Option<u32>,
+    pub not: bool,
+    pub fwmark: Option<u32>,
+    pub table: u32,
+    pub suppress_prefixlen: Option<u32>,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", if self.ipv6 { "-6" } else { "-4" })?;
+        if let Some(priority) = self.priority {
+            write!(f, " priority {}", priority)?;
+        }
+        if self.not {
+            write!(f, " not")?;
+        }
+        if let Some(fwmark) = self.fwmark {
+            write!(f, " fwmark {:#x}", fwmark)?;
+        }
+        write!(f, " table {}", self.table)?;
+        if let Some(len) = self.suppress_prefixlen {
+            write!(f, " suppress_prefixlength {}", len)?;
+        }
+        Ok(())
+    }
+}
+
+/// Path of a network namespace given by a name of `ip netns` or a path.
+pub fn netns_path(netns: &str) -> PathBuf {
+    if netns.contains('/') {
+        netns.into()
+    } else {
+        Path::new("/var/run/netns").join(netns)
+    }
+}
+
+/// Runs `f` in the network namespace, or in the current one if `netns` is not set.
+/// Sockets and devices created by `f` stay in the namespace.
+pub fn in_netns<T, E: From<Error>>(
+    netns: Option<&str>,
+    f: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let netns = match netns {
+        Some(netns) => netns,
+        None => return f(),
+    };
+
+    let enter = |path: &Path| -> Result<(), Error> {
+        let ns = File::open(path).map_err(|e| Error::Netns(path.display().to_string(), e))?;
+        setns(ns, CloneFlags::CLONE_NEWNET)
+            .map_err(|e| Error::Netns(path.display().to_string(), e.into()))
+    };
+
+    let orig = Path::new("/proc/thread-self/ns/net");
+    let orig = File::open(orig).map_err(|e| Error::Netns(orig.display().to_string(), e))?;
+    enter(&netns_path(netns))?;
+
+    let result = f();
+
+    setns(orig, CloneFlags::CLONE_NEWNET).map_err(|e| Error::Netns("restore".into(), e.into()))?;
+    result
+}
+
+/// Resolves a routing table name or number.
+pub fn table_id(name: &str) -> Result<u32, Error> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+
+    match name {
+        "default" => return Ok(253),
+        "main" => return Ok(RT_TABLE_MAIN),
+        "local" => return Ok(255),
+        _ => {}
+    }
+
+    ["/etc/iproute2/rt_tables", "/usr/share/iproute2/rt_tables"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .find_map(|s| {
+            s.lines().find_map(|line| {
+                let mut it = line.split('#').next()?.split_whitespace();
+                match (it.next(), it.next()) {
+                    (Some(id), Some(n)) if n == name => id.parse().ok(),
+                    _ => None,
+                }
+            })
+        })
+        .ok_or_else(|| Error::InvalidArg(format!("unknown route table {:?}", name)))
+}
+
+fn if_index(dev: &str) -> io::Result<u32> {
+    let name = CString::new(dev).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+fn if_name(index: u32) -> io::Result<String> {
+    let mut buf = [0u8; libc::IF_NAMESIZE];
+    if unsafe { libc::if_indextoname(index, buf.as_mut_ptr() as *mut _) }.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+fn family(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => libc::AF_INET as u8,
+        IpAddr::V6(_) => libc::AF_INET6 as u8,
+    }
+}
+
+fn addr_bytes(addr: &IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    }
+}
+
+fn bytes_addr(data: &[u8]) -> Option<IpAddr> {
+    match data.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(data).ok()?)),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(data).ok()?)),
+        _ => None,
+    }
+}
+
+fn unspecified(ipv6: bool) -> IpAddr {
+    if ipv6 {
+        Ipv6Addr::UNSPECIFIED.into()
+    } else {
+        Ipv4Addr::UNSPECIFIED.into()
+    }
+}
+
+// Parses the body of a RTM_NEWROUTE message.
+fn parse_route(body: &[u8]) -> Option<Route> {
+    if body.len() < 12 {
+        return None;
+    }
+
+    let ipv6 = body[0] == libc::AF_INET6 as u8;
+    let mut dst = unspecified(ipv6);
+    let mut route = Route {
+        dst: IpNet::new(dst, body[1]).ok()?,
+        gateway: None,
+        dev: None,
+        table: Some(body[4] as u32),
+        metric: None,
+    };
+
+    let mut attrs = &body[12..];
+    while attrs.len() >= 4 {
+        let len = u16::from_ne_bytes([attrs[0], attrs[1]]) as usize;
+        let ty = u16::from_ne_bytes([attrs[2], attrs[3]]);
+        if len < 4 || len > attrs.len() {
+            break;
+        }
+
+        let data = &attrs[4..len];
+        let v = data.try_into().map(u32::from_ne_bytes).ok();
+        match ty {
+            RTA_DST => dst = bytes_addr(data)?,
+            RTA_GATEWAY => route.gateway = bytes_addr(data),
+            RTA_OIF => route.dev = v.and_then(|i| if_name(i).ok()),
+            RTA_PRIORITY => route.metric = v,
+            RTA_TABLE => route.table = v,
+            _ => {}
+        }
+        attrs = &attrs[((len + 3) & !3).min(attrs.len())..];
+    }
+
+    route.dst = IpNet::new(dst, route.dst.prefix_len()).ok()?;
+    Some(route)
+}
+
+// A netlink request, the header is filled in when sent.
+struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    fn new(ty: u16, flags: u16) -> Self {
+        let mut buf = vec![0u8; NLMSG_HDRLEN];
+        buf[4..6].copy_from_slice(&ty.to_ne_bytes());
+        buf[6..8].copy_from_slice(&(flags | NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+        Self { buf }
+    }
+
+    fn push(&mut self, data: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(data);
+        self
+    }
+
+    fn attr(&mut self, ty: u16, data: &[u8]) -> &mut Self {
+        let len = 4 + data.len();
+        self.buf.extend_from_slice(&(len as u16).to_ne_bytes());
+        self.buf.extend_from_slice(&ty.to_ne_bytes());
+        self.buf.extend_from_slice(data);
+        self.buf.resize(self.buf.len() + (4 - len % 4) % 4, 0);
+        self
+    }
+
+    fn attr_u32(&mut self, ty: u16, v: u32) -> &mut Self {
+        self.attr(ty, &v.to_ne_bytes())
+    }
+}
+
+/// A NETLINK_ROUTE socket, the configuration applies to the network namespace
+/// the socket was created in. Interface names are resolved in the namespace of
+/// the calling thread, so a socket of another one is used within `in_netns`.
+pub struct Netlink {
+    fd: OwnedFd,
+    seq: Cell<u32>,
+}
+
+impl Netlink {
+    pub fn new() -> Result<Self, Error> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut sa: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        sa.nl_family = libc::AF_NETLINK as u16;
+        if unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &sa as *const _ as *const _,
+                mem::size_of_val(&sa) as libc::socklen_t,
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(Self {
+            fd,
+            seq: Cell::new(0),
+        })
+    }
+
+    pub fn set_mtu(&self, dev: &str, mtu: u16) -> Result<(), Error> {
+        self.link_mtu(dev, mtu)
+            .map_err(|e| Error::Netlink(format!("set mtu {} dev {}", mtu, dev), e))
+    }
+
+    pub fn add_addr(&self, addr: IpNet, dev: &str) -> Result<(), Error> {
+        self.addr(RTM_NEWADDR, NLM_F_CREATE | NLM_F_EXCL, addr, dev)
+            .map_err(|e| Error::Netlink(format!("add address {} dev {}", addr, dev), e))
+    }
+
+    pub fn del_addr(&self, addr: IpNet, dev: &str) -> Result<(), Error> {
+        self.addr(RTM_DELADDR, 0, addr, dev)
+            .map_err(|e| Error::Netlink(format!("del address {} dev {}", addr, dev), e))
+    }
+
+    pub fn add_route(&self, route: &Route) -> Result<(), Error> {
+        self.route(RTM_NEWROUTE, NLM_F_CREATE | NLM_F_EXCL, route)
+            .map_err(|e| Error::Netlink(format!("add route {}", route), e))
+    }
+
+    pub fn del_route(&self, route: &Route) -> Result<(), Error> {
+        self.route(RTM_DELROUTE, 0, route)
+            .map_err(|e| Error::Netlink(format!("del route {}", route), e))
+    }
+
+    pub fn add_rule(&self, rule: &Rule) -> Result<(), Error> {
+        self.rule(RTM_NEWRULE, NLM_F_CREATE | NLM_F_EXCL, rule)
+            .map_err(|e| Error::Netlink(format!("add rule {}", rule), e))
+    }
+
+    pub fn del_rule(&self, rule: &Rule) -> Result<(), Error> {
+        self.rule(RTM_DELRULE, 0, rule)
+            .map_err(|e| Error::Netlink(format!("del rule {}", rule), e))
+    }
+
+    /// Looks up the route to `dst` as the kernel would do for an outgoing packet.
+    pub fn get_route(&self, dst: IpAddr) -> Result<Route, Error> {
+        let mut msg = Message::new(RTM_GETROUTE, 0);
+        msg.push(&[family(&dst), if dst.is_ipv6() { 128 } else { 32 }])
+            .push(&[0; 10])
+            .attr(RTA_DST, &addr_bytes(&dst));
+
+        self.request(msg)
+            .and_then(|replies| {
+                replies
+                    .iter()
+                    .find_map(|body| parse_route(body))
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData))
+            })
+            .map_err(|e| Error::Netlink(format!("get route {}", dst), e))
+    }
+
+    /// Default routes of the main table, with the lowest metric first.
+    pub fn default_routes(&self, ipv6: bool) -> Result<Vec<Route>, Error> {
+        let mut msg = Message::new(RTM_GETROUTE, NLM_F_DUMP);
+        msg.push(&[family(&unspecified(ipv6))]).push(&[0; 11]);
+
+        let mut routes: Vec<Route> = self
+            .request(msg)
+            .map_err(|e| Error::Netlink("get default routes".into(), e))?
+            .iter()
+            .filter_map(|body| parse_route(body))
+            .filter(|r| r.dst.prefix_len() == 0 && r.table == Some(RT_TABLE_MAIN))
+            .collect();
+        routes.sort_by_key(|r| r.metric.unwrap_or(0));
+        Ok(routes)
+    }
+
+    fn link_mtu(&self, dev: &str, mtu: u16) -> io::Result<()> {
+        let index = if_index(dev)?;
+
+        // struct ifinfomsg, no flags changed
+        let mut msg = Message::new(RTM_NEWLINK, 0);
+        msg.push(&[libc::AF_UNSPEC as u8, 0, 0, 0])
+            .push(&index.to_ne_bytes())
+            .push(&[0; 8])
+            .attr_u32(IFLA_MTU, mtu as u32);
+        self.request(msg).map(|_| ())
+    }
+
+    fn addr(&self, ty: u16, flags: u16, addr: IpNet, dev: &str) -> io::Result<()> {
+        let index = if_index(dev)?;
+        let ip = addr.addr();
+
+        // struct ifaddrmsg
+        let mut msg = Message::new(ty, flags);
+        msg.push(&[family(&ip), addr.prefix_len(), 0, RT_SCOPE_UNIVERSE])
+            .push(&index.to_ne_bytes())
+            .attr(IFA_LOCAL, &addr_bytes(&ip))
+            .attr(IFA_ADDRESS, &addr_bytes(&ip));
+        self.request(msg).map(|_| ())
+    }
+
+    fn route(&self, ty: u16, flags: u16, route: &Route) -> io::Result<()> {
+        let dst = route.dst.addr();
+        let table = route.table.unwrap_or(RT_TABLE_MAIN);
+        let (protocol, scope, kind) = match ty {
+            RTM_DELROUTE => (0, RT_SCOPE_NOWHERE, 0),
+            _ if route.gateway.is_none() => (RTPROT_BOOT, RT_SCOPE_LINK, RTN_UNICAST),
+            _ => (RTPROT_BOOT, RT_SCOPE_UNIVERSE, RTN_UNICAST),
+        };
+
+        // struct rtmsg, tables above 255 are only given by RTA_TABLE
+        let mut msg = Message::new(ty, flags);
+        msg.push(&[
+            family(&dst),
+            route.dst.prefix_len(),
+            0,
+            0,
+            if table < 256 { table as u8 } else { 0 },
+            protocol,
+            scope,
+            kind,
+        ])
+        .push(&0u32.to_ne_bytes())
+        .attr(RTA_DST, &addr_bytes(&route.dst.network()))
+        .attr_u32(RTA_TABLE, table);
+
+        if let Some(gw) = route.gateway {
+            msg.attr(RTA_GATEWAY, &addr_bytes(&gw));
+        }
+        if let Some(ref dev) = route.dev {
+            let index = if_index(dev)?;
+            msg.attr_u32(RTA_OIF, index);
+        }
+        if let Some(metric) = route.metric {
+            msg.attr_u32(RTA_PRIORITY, metric);
+        }
+        self.request(msg).map(|_| ())
+    }
+
+    fn rule(&self, ty: u16, flags: u16, rule: &Rule) -> io::Result<()> {
+        let family = if rule.ipv6 {
+            libc::AF_INET6
+        } else {
+            libc::AF_INET
+        };
+
+        // struct fib_rule_hdr
+        let mut msg = Message::new(ty, flags);
+        msg.push(&[
+            family as u8,
+            0,
+            0,
+            0,
+            if rule.table < 256 {
+                rule.table as u8
+            } else {
+                0
+            },
+            0,
+            0,
+            FR_ACT_TO_TBL,
+        ])
+        .push(&(if rule.not { FIB_RULE_INVERT } else { 0 }).to_ne_bytes())
+        .attr_u32(FRA_TABLE, rule.table);
+
+        if let Some(priority) = rule.priority {
+            msg.attr_u32(FRA_PRIORITY, priority);
+        }
+        if let Some(fwmark) = rule.fwmark {
+            msg.attr_u32(FRA_FWMARK, fwmark);
+        }
+        if let Some(len) = rule.suppress_prefixlen {
+            msg.attr_u32(FRA_SUPPRESS_PREFIXLEN, len);
+        }
+        self.request(msg).map(|_| ())
+    }
+
+    // Sends the request and waits for the kernel ack, returns the bodies of the replies.
+    fn request(&self, mut msg: Message) -> io::Result<Vec<Vec<u8>>> {
+        let seq = self.seq.get().wrapping_add(1);
+        self.seq.set(seq);
+
+        let len = msg.buf.len() as u32;
+        msg.buf[0..4].copy_from_slice(&len.to_ne_bytes());
+        msg.buf[8..12].copy_from_slice(&seq.to_ne_bytes());
+
+        let mut sa: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        sa.nl_family = libc::AF_NETLINK as u16;
+        if unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                msg.buf.as_ptr() as *const _,
+                msg.buf.len(),
+                0,
+                &sa as *const _ as *const _,
+                mem::size_of_val(&sa) as libc::socklen_t,
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut replies = Vec::new();
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut _,
+                    buf.len(),
+                    0,
+                )
+            };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if let Some(errno) = parse_reply(&buf[..n as usize], seq, &mut replies) {
+                return match errno {
+                    0 => Ok(replies),
+                    e => Err(io::Error::from_raw_os_error(-e)),
+                };
+            }
+        }
+    }
+}
+
+// Collects the replies of `seq`, returns the error code once the request is done, 0 is
+// an ack or the end of a dump.
+fn parse_reply(mut buf: &[u8], seq: u32, replies: &mut Vec<Vec<u8>>) -> Option<i32> {
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = u32::from_ne_bytes(buf[0..4].try_into().unwrap()) as usize;
+        let ty = u16::from_ne_bytes(buf[4..6].try_into().unwrap());
+        let msg_seq = u32::from_ne_bytes(buf[8..12].try_into().unwrap());
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            return None;
+        }
+
+        if msg_seq == seq {
+            match ty {
+                NLMSG_ERROR if len >= NLMSG_HDRLEN + 4 => {
+                    let data = &buf[NLMSG_HDRLEN..NLMSG_HDRLEN + 4];
+                    return Some(i32::from_ne_bytes(data.try_into().unwrap()));
+                }
+                NLMSG_DONE => return Some(0),
+                _ => replies.push(buf[NLMSG_HDRLEN..len].to_vec()),
+            }
+        }
+
+        buf = &buf[((len + 3) & !3).min(buf.len())..];
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    #[test]
+    fn test() {
+        assert_eq!(table_id("main").unwrap(), RT_TABLE_MAIN);
+        assert_eq!(table_id("100").unwrap(), 100);
+        assert!(table_id("no-such-table").is_err());
+
+        assert_eq!(netns_path("blue"), Path::new("/var/run/netns/blue"));
+        assert_eq!(netns_path("/proc/1/ns/net"), Path::new("/proc/1/ns/net"));
+        assert_eq!(in_netns(None, || Ok::<_, Error>(1)).unwrap(), 1);
+        assert!(in_netns(Some("/no/such/netns"), || Ok::<_, Error>(1)).is_err());
+
+        let mut route = Route::new(
+            "10.1.0.0/16".parse().unwrap(),
+            "tun0",
+            &Some("100".into()),
+            &Some("5".into()),
+        )
+        .unwrap();
+        route.gateway = Some("10.0.0.1".parse().unwrap());
+        assert_eq!(
+            route.to_string(),
+            "10.1.0.0/16 via 10.0.0.1 dev tun0 table 100 metric 5"
+        );
+        assert!(Route::new(route.dst, "tun0", &None, &Some("x".into())).is_err());
+
+        let mut msg = Message::new(RTM_NEWROUTE, 0);
+        msg.attr(RTA_DST, &[10, 1, 0, 0]).attr(RTA_GATEWAY, &[1]);
+        assert_eq!(msg.buf.len(), NLMSG_HDRLEN + 8 + 8);
+
+        // NLMSG_ERROR with -EEXIST for seq 7
+        let mut ack = Message::new(NLMSG_ERROR, 0);
+        ack.push(&(-libc::EEXIST).to_ne_bytes());
+        let len = ack.buf.len() as u32;
+        ack.buf[0..4].copy_from_slice(&len.to_ne_bytes());
+        ack.buf[8..12].copy_from_slice(&7u32.to_ne_bytes());
+        let mut replies = Vec::new();
+        assert_eq!(parse_reply(&ack.buf, 7, &mut replies), Some(-libc::EEXIST));
+        assert_eq!(parse_reply(&ack.buf, 8, &mut replies), None);
+
+        let mut reply = Message::new(RTM_NEWROUTE, 0);
+        reply
+            .push(&[libc::AF_INET as u8, 0, 0, 0, 254, 0, 0, 1])
+            .push(&[0; 4])
+            .attr(RTA_GATEWAY, &[192, 168, 1, 1])
+            .attr_u32(RTA_PRIORITY, 100);
+        let route = parse_route(&reply.buf[NLMSG_HDRLEN..]).unwrap();
+        assert_eq!(
+            route.to_string(),
+            "0.0.0.0/0 via 192.168.1.1 table 254 metric 100"
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! Path MTU discovery of a client, with padded echo requests.
+//!
+//! The datagram sizes are searched in halves, from one any path carries up to
+//! one of an Ethernet MTU. A size is given up after the probes of all tries are
+//! lost, the largest one acknowledged is the datagram the path carries.
+
+use crate::msg::{IP_DATA_HEADER_SIZE, MSG_HEADER_SIZE};
+use libc::c_int;
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+const PROBE_TRIES: u8 = 3;
+// the path may change, it is searched again after the interval
+const INTERVAL: Duration = Duration::from_secs(600);
+// the ciphers pad to their block size, the sizes are multiples of it
+const STEP: u16 = 16;
+const MAX_PMTU: u16 = 1500;
+const ETHER_HEADER_SIZE: u16 = 14;
+
+/// Sends a probe with the don't fragment bit set, regardless of the path MTU
+/// known to the kernel, so one larger than the path is lost. The other datagrams
+/// of `socket` keep the mode they had, and are fragmented if the path needs it.
+pub fn send_probe(socket: &UdpSocket, buf: &[u8]) -> io::Result<usize> {
+    let (level, name, probe) = match socket.local_addr()? {
+        SocketAddr::V4(_) => (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_PROBE,
+        ),
+        SocketAddr::V6(_) => (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_PROBE,
+        ),
+    };
+    let fd = socket.as_raw_fd();
+    let orig = get_option(fd, level, name)?;
+    set_option(fd, level, name, probe)?;
+    let result = socket.send(buf);
+    set_option(fd, level, name, orig).and(result)
+}
+
+fn get_option(fd: RawFd, level: c_int, name: c_int) -> io::Result<c_int> {
+    let mut value: c_int = 0;
+    let mut len = mem::size_of_val(&value) as libc::socklen_t;
+    let ret =
+        unsafe { libc::getsockopt(fd, level, name, &mut value as *mut _ as *mut _, &mut len) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+fn set_option(fd: RawFd, level: c_int, name: c_int, value: c_int) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const _ as *const _,
+            mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+struct Probe {
+    id: u32,
+    size: u16,
+    sent: Instant,
+    tries: u8,
+}
+
+pub struct Discovery {
+    // IP and UDP headers
+    overhead: u16,
+    // a datagram of the minimum MTU of the IP version
+    min: u16,
+    // the largest size acknowledged and the smallest given up
+    low: u16,
+    high: u16,
+    probe: Option<Probe>,
+    // end of the last search and the datagram size found
+    done: Option<Instant>,
+    datagram: Option<u16>,
+}
+
+impl Discovery {
+    /// Searches the path to `peer`.
+    pub fn new(peer: &SocketAddr) -> Self {
+        let (overhead, min_pmtu) = match peer {
+            SocketAddr::V4(_) => (20 + 8, 576),
+            SocketAddr::V6(_) => (40 + 8, 1280),
+        };
+        let mut discovery = Self {
+            overhead,
+            min: (min_pmtu - overhead) / STEP * STEP,
+            low: 0,
+            high: 0,
+            probe: None,
+            done: None,
+            datagram: None,
+        };
+        discovery.restart();
+        discovery
+    }
+
+    fn restart(&mut self) {
+        self.low = self.min;
+        self.high = (MAX_PMTU - self.overhead) / STEP * STEP + STEP;
+        self.probe = None;
+        self.done = None;
+    }
+
+    /// The path MTU found by the last search.
+    pub fn pmtu(&self) -> Option<u16> {
+        self.datagram.map(|v| v + self.overhead)
+    }
+
+    /// The largest interface MTU whose packets fit the path.
+    pub fn tun_mtu(&self, tap: bool) -> Option<u16> {
+        let header = (MSG_HEADER_SIZE + IP_DATA_HEADER_SIZE) as u16;
+        let header = if tap {
+            header + ETHER_HEADER_SIZE
+        } else {
+            header
+        };
+        self.datagram.map(|v| v - header)
+    }
+
+    /// The size of the probe to send now, a new one is sent with `id` and a
+    /// lost one again with its own.
+    pub fn next(&mut self, id: u32) -> Option<(u32, u16)> {
+        self.next_at(id, Instant::now())
+    }
+
+    fn next_at(&mut self, id: u32, now: Instant) -> Option<(u32, u16)> {
+        if let Some(ref mut probe) = self.probe {
+            if now.saturating_duration_since(probe.sent) < PROBE_TIMEOUT {
+                return None;
+            }
+            if probe.tries < PROBE_TRIES {
+                probe.tries += 1;
+                probe.sent = now;
+                return Some((probe.id, probe.size));
+            }
+            self.high = probe.size;
+            self.probe = None;
+        }
+
+        if let Some(done) = self.done {
+            if now.saturating_duration_since(done) < INTERVAL {
+                return None;
+            }
+            self.restart();
+        }
+
+        if self.high - self.low <= STEP {
+            self.done = Some(now);
+            self.datagram = Some(self.low);
+            return None;
+        }
+
+        let size = (self.low + self.high) / 2 / STEP * STEP;
+        self.probe = Some(Probe {
+            id,
+            size,
+            sent: now,
+            tries: 1,
+        });
+        Some((id, size))
+    }
+
+    /// Takes the ack of a probe, false if `id` is not the one in flight.
+    pub fn ack(&mut self, id: u32) -> bool {
+        match self.probe.take_if(|v| v.id == id) {
+            Some(probe) => {
+                self.low = probe.size;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    #[test]
+    fn test() {
+        let peer = "1.2.3.4:1234".parse().unwrap();
+        let mut discovery = Discovery::new(&peer);
+        // a path of 1400 bytes
+        let mut id = 0;
+        let mut now = Instant::now();
+        while discovery.pmtu().is_none() {
+            id += 1;
+            match discovery.next_at(id, now) {
+                Some((id, size)) if size + 28 <= 1400 => assert!(discovery.ack(id)),
+                _ => now += PROBE_TIMEOUT,
+            }
+        }
+        assert_eq!(discovery.datagram, Some(1360));
+        assert_eq!(discovery.pmtu(), Some(1388));
+        assert_eq!(discovery.tun_mtu(false), Some(1336));
+        assert!(!discovery.ack(id));
+
+        // searched again after the interval
+        assert_eq!(discovery.next_at(1, now), None);
+        let (_, size) = discovery.next_at(1, now + INTERVAL).unwrap();
+        assert_eq!(size, (544 + 1488) / 2 / STEP * STEP);
+        assert!(!discovery.ack(2));
+        assert_eq!(discovery.next_at(2, now + INTERVAL), None);
+        // lost, sent again with its id
+        let retry = discovery.next_at(3, now + INTERVAL + PROBE_TIMEOUT);
+        assert_eq!(retry, Some((1, size)));
+        assert_eq!(discovery.pmtu(), Some(1388));
+    }
+}
@@ -1,45 +1,88 @@
+use log::warn;
 use std::error::Error;
+use std::io::{Read, Write};
 use std::mem;
 use std::mem::MaybeUninit;
-use std::os::unix::io::RawFd;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
 use std::{io, ptr};
 
 extern crate libc;
 
 type Result = std::result::Result<(), Box<dyn Error>>;
 
+/// Size of the buffers the reactors receive into, the largest packet of a tun
+/// interface and the largest datagram.
+pub const BUFFER_SIZE: usize = 65536;
+
 pub trait Reactor {
+    fn tun_fd(&self) -> RawFd;
     fn socket_fd(&self) -> Option<RawFd>;
+    fn start(&mut self) -> Result;
     fn keepalive(&mut self) -> Result;
     fn tunnel_recv(&self) -> Result;
     fn network_recv(&self) -> Result;
-    fn handle_control_connection(&mut self, fd: RawFd) -> Result;
+    fn handle_control(&mut self, cmd: &str) -> String;
+    fn reload(&mut self) -> Result;
+    fn shutdown(&mut self) -> Result;
 }
 
-pub fn poll<T: Reactor>(
-    tun_fd: RawFd,
+/// Named reactors driven by one event loop.
+pub type Reactors = [(String, Box<dyn Reactor>)];
+
+pub fn poll(
+    reactors: &mut Reactors,
     control_fd: Option<RawFd>,
     exit_signal: Option<RawFd>,
-    mut reactor: T,
+    reload_signal: Option<RawFd>,
 ) -> Result {
+    if let Some(e) = reactors.iter_mut().find_map(|(_, r)| r.start().err()) {
+        // none runs then, all are configured and shut down
+        for (_, reactor) in reactors.iter_mut() {
+            let _ = reactor.shutdown();
+        }
+        return Err(e);
+    }
+
     let mut fd_set = unsafe { MaybeUninit::assume_init(MaybeUninit::<libc::fd_set>::uninit()) };
+    // a failing tunnel is shut down, the others keep running
+    let mut failed = vec![false; reactors.len()];
 
     loop {
-        let socket_fd = reactor.socket_fd().unwrap_or(0);
         let control_fd = control_fd.unwrap_or(0);
         let exit_signal_fd = exit_signal.unwrap_or(0);
+        let reload_signal_fd = reload_signal.unwrap_or(0);
 
-        let nfds = [tun_fd, socket_fd, control_fd, exit_signal_fd]
+        let tun_fds: Vec<RawFd> = reactors
             .iter()
-            .max()
-            .unwrap()
-            + 1;
+            .zip(&failed)
+            .map(|((_, r), failed)| if *failed { 0 } else { r.tun_fd() })
+            .collect();
+        let socket_fds: Vec<RawFd> = reactors
+            .iter()
+            .zip(&failed)
+            .map(|((_, r), failed)| {
+                if *failed {
+                    0
+                } else {
+                    r.socket_fd().unwrap_or(0)
+                }
+            })
+            .collect();
+
+        let signal_fds = [control_fd, exit_signal_fd, reload_signal_fd];
+        let fds = tun_fds
+            .iter()
+            .chain(socket_fds.iter())
+            .chain(signal_fds.iter());
+
+        let nfds = fds.clone().max().unwrap() + 1;
 
         unsafe {
             libc::FD_ZERO(&mut fd_set);
-            for fd in [tun_fd, socket_fd, control_fd, exit_signal_fd] {
-                if fd != 0 {
-                    libc::FD_SET(fd, &mut fd_set);
+            for fd in fds {
+                if *fd != 0 {
+                    libc::FD_SET(*fd, &mut fd_set);
                 }
             }
         }
@@ -63,17 +106,38 @@ pub fn poll<T: Reactor>(
         }
 
         if exit_signal_fd != 0 && unsafe { libc::FD_ISSET(exit_signal_fd, &fd_set) } {
+            for (i, (_, reactor)) in reactors.iter_mut().enumerate() {
+                if !failed[i] {
+                    let _ = reactor.shutdown();
+                }
+            }
             break;
         }
 
-        reactor.keepalive()?;
-
-        if unsafe { libc::FD_ISSET(tun_fd, &fd_set) } {
-            reactor.tunnel_recv()?
+        if reload_signal_fd != 0 && unsafe { libc::FD_ISSET(reload_signal_fd, &fd_set) } {
+            // drain the signal, a signalfd requires room for a whole siginfo
+            let mut buf = [0u8; 128];
+            unsafe { libc::read(reload_signal_fd, buf.as_mut_ptr() as *mut _, buf.len()) };
+            for (i, (_, reactor)) in reactors.iter_mut().enumerate() {
+                if !failed[i] {
+                    let _ = reactor.reload();
+                }
+            }
         }
 
-        if socket_fd != 0 && unsafe { libc::FD_ISSET(socket_fd, &fd_set) } {
-            reactor.network_recv()?
+        for (i, (name, reactor)) in reactors.iter_mut().enumerate() {
+            if failed[i] {
+                continue;
+            }
+
+            if let Err(e) = run_once(reactor.as_mut(), tun_fds[i], socket_fds[i], &fd_set) {
+                warn!("tunnel {} fail, shutting it down. {}", name, e);
+                let _ = reactor.shutdown();
+                failed[i] = true;
+                if failed.iter().all(|v| *v) {
+                    return Err(e);
+                }
+            }
         }
 
         if control_fd != 0 && unsafe { libc::FD_ISSET(control_fd, &fd_set) } {
@@ -82,10 +146,63 @@ pub fn poll<T: Reactor>(
             let fd =
                 unsafe { libc::accept(control_fd, &mut storage as *mut _ as *mut _, &mut len) };
             if fd > 0 {
-                let _ = reactor.handle_control_connection(fd);
+                handle_control_connection(reactors, fd);
             }
         }
     }
 
     Ok(())
 }
+
+fn run_once(
+    reactor: &mut dyn Reactor,
+    tun_fd: RawFd,
+    socket_fd: RawFd,
+    fd_set: &libc::fd_set,
+) -> Result {
+    reactor.keepalive()?;
+
+    if unsafe { libc::FD_ISSET(tun_fd, fd_set) } {
+        reactor.tunnel_recv()?
+    }
+
+    if socket_fd != 0 && unsafe { libc::FD_ISSET(socket_fd, fd_set) } {
+        reactor.network_recv()?
+    }
+
+    Ok(())
+}
+
+fn handle_control_connection(reactors: &mut Reactors, fd: RawFd) {
+    let mut us = unsafe { UnixStream::from_raw_fd(fd) };
+    let mut buf = [0u8; 256];
+
+    if let Ok(n) = us.read(&mut buf) {
+        let resp = match std::str::from_utf8(&buf[..n]) {
+            Ok(s) => dispatch(reactors, s.trim()),
+            Err(_) => "Invalid UTF-8 sequence\n".to_string(),
+        };
+
+        let _ = us.write(resp.as_bytes());
+    }
+}
+
+// A command may be prefixed with the tunnel name, e.g. "office show-info",
+// otherwise it is sent to all tunnels.
+fn dispatch(reactors: &mut Reactors, cmd: &str) -> String {
+    if let Some((name, cmd)) = cmd.split_once(' ') {
+        return match reactors.iter_mut().find(|(n, _)| n == name) {
+            Some((_, reactor)) => reactor.handle_control(cmd.trim()),
+            None => format!("Unknown tunnel: {}\n", name),
+        };
+    }
+
+    match reactors {
+        [(_, reactor)] => reactor.handle_control(cmd),
+        _ => reactors
+            .iter_mut()
+            .map(|(name, reactor)| format!("[{}]\n{}", name, reactor.handle_control(cmd)))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
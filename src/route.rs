@@ -1,4 +1,7 @@
 use {
+    crate::config::{AdmissionPolicy, VipPolicy},
+    crate::shaper::{Limit, TokenBucket},
+    crate::trie::PrefixTrie,
     crate::util::pretty_duration,
     ipnet::IpNet,
     log::{debug, info},
@@ -7,6 +10,7 @@ use {
         cell::RefCell,
         collections::HashMap,
         fmt::{Display, Formatter},
+        mem,
         net::{IpAddr, SocketAddr},
         num::Wrapping,
         rc::Rc,
@@ -19,6 +23,11 @@ pub struct RealAddr {
     pub addr: SocketAddr,
     pub last_recv: Instant,
     pub xmit_seq: Wrapping<u16>,
+    // identity announced by the client in its echo
+    pub client_id: Option<u64>,
+    // rate limits of the traffic from and to the client
+    pub ingress: TokenBucket,
+    pub egress: TokenBucket,
 }
 
 impl RealAddr {
@@ -27,6 +36,9 @@ impl RealAddr {
             addr,
             last_recv: Instant::now(),
             xmit_seq: Wrapping(thread_rng().next_u32() as u16),
+            client_id: None,
+            ingress: Default::default(),
+            egress: Default::default(),
         }
     }
 
@@ -60,6 +72,30 @@ impl RefRA {
     pub fn next_seq(&self) -> u16 {
         self.0.borrow_mut().next_seq()
     }
+
+    pub fn client_id(&self) -> Option<u64> {
+        self.0.borrow().client_id
+    }
+
+    // Sets the identity of the client, the first one announced is kept.
+    pub fn set_client_id(&self, id: u64) {
+        self.0.borrow_mut().client_id.get_or_insert(id);
+    }
+
+    // Takes the size of a packet from the client off its ingress bucket.
+    pub fn ingress(&self, limit: &Limit, len: usize) -> bool {
+        self.0.borrow_mut().ingress.take(limit, len)
+    }
+
+    // Takes the size of a packet to the client off its egress bucket.
+    pub fn egress(&self, limit: &Limit, len: usize) -> bool {
+        self.0.borrow_mut().egress.take(limit, len)
+    }
+
+    // Whether both are the same client, known by its identity.
+    fn is_same_client(&self, other: &RefRA) -> bool {
+        self.client_id().is_some() && self.client_id() == other.client_id()
+    }
 }
 
 #[derive(Clone)]
@@ -79,11 +115,38 @@ impl VirtualAddr {
     }
 }
 
+// A client virtual address appeared or is gone.
+pub enum VipEvent {
+    New(IpAddr, SocketAddr),
+    Gone(IpAddr, SocketAddr),
+    // A claim of a vip bound to another client was rejected.
+    Conflict(IpAddr, SocketAddr),
+    // A new client, or vip if set, was rejected by an admission limit.
+    Reject(Option<IpAddr>, SocketAddr, &'static str),
+    // The oldest client, or vip if set, idle for `Admission::idle` was evicted for a new one.
+    Evict(Option<IpAddr>, SocketAddr, &'static str),
+}
+
+// Limits of the clients and vips in the table, unlimited if not set.
+#[derive(Default, Clone, Copy)]
+pub struct Admission {
+    pub max_clients: Option<usize>,
+    pub max_clients_per_ip: Option<usize>,
+    pub max_vips: Option<usize>,
+    pub policy: AdmissionPolicy,
+    // how long an entry must be idle to be evicted
+    pub idle: Duration,
+}
+
 #[derive(Default)]
 pub struct RouteTable {
     ra_map: HashMap<SocketAddr, RefRA>,
     va_map: HashMap<IpAddr, VirtualAddr>,
-    vt_routes: Vec<(IpNet, IpAddr)>,
+    // gateway vip and metric of the routed networks
+    vt_routes: PrefixTrie<(IpAddr, u32)>,
+    events: Vec<VipEvent>,
+    vip_policy: VipPolicy,
+    admission: Admission,
 }
 
 impl RouteTable {
@@ -93,19 +156,197 @@ impl RouteTable {
     }
 
     // Adds a new route to the route table.
-    pub fn add_route(&mut self, net: IpNet, gw: IpAddr) {
-        self.vt_routes.push((net, gw));
+    pub fn add_route(&mut self, net: IpNet, gw: IpAddr, metric: u32) {
+        self.vt_routes.insert(net, (gw, metric));
     }
 
-    // Retrieves or adds a real address to the map.
-    pub fn get_or_add_ra(&mut self, addr: &SocketAddr) -> &RefRA {
-        self.ra_map
+    pub fn set_vip_policy(&mut self, policy: VipPolicy) {
+        self.vip_policy = policy;
+    }
+
+    pub fn set_admission(&mut self, admission: Admission) {
+        self.admission = admission;
+    }
+
+    // Removes all routes, learned virtual addresses are kept.
+    pub fn clear_routes(&mut self) {
+        self.vt_routes.clear();
+    }
+
+    // Iterates over all known real addresses.
+    pub fn real_addrs(&self) -> impl Iterator<Item = &RefRA> {
+        self.ra_map.values()
+    }
+
+    // Iterates over all known virtual addresses.
+    pub fn virtual_addrs(&self) -> impl Iterator<Item = &VirtualAddr> {
+        self.va_map.values()
+    }
+
+    // Restores a virtual address saved before a restart, last received from at
+    // `last_recv`. The admission limits don't apply.
+    pub fn restore_va(
+        &mut self,
+        va: IpAddr,
+        addr: SocketAddr,
+        client_id: Option<u64>,
+        last_recv: Instant,
+    ) {
+        let new = !self.ra_map.contains_key(&addr);
+        let ra = self
+            .ra_map
+            .entry(addr)
+            .or_insert_with(|| RefRA::new(addr))
+            .clone();
+        {
+            let mut real = ra.0.borrow_mut();
+            if new || real.last_recv < last_recv {
+                real.last_recv = last_recv;
+            }
+            if client_id.is_some() {
+                real.client_id = client_id;
+            }
+        }
+
+        debug!("Restore vip [{:?}] at [{:?}]", va, addr);
+        self.events.push(VipEvent::New(va, addr));
+        self.va_map.insert(va, VirtualAddr { va, ra, last_recv });
+    }
+
+    // Removes a real address and the virtual addresses at it.
+    pub fn remove_ra(&mut self, addr: &SocketAddr) {
+        if self.ra_map.remove(addr).is_some() {
+            debug!("Remove client [{:?}]", addr);
+        }
+        let events = &mut self.events;
+        self.va_map.retain(|_, v| {
+            if v.ra.addr() == *addr {
+                debug!("Recycle vip [{:?}] at [{:}]", v.va, addr);
+                events.push(VipEvent::Gone(v.va, *addr));
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    // Virtual addresses at a real address.
+    pub fn vips_at(&self, addr: &SocketAddr) -> Vec<IpAddr> {
+        self.va_map
+            .values()
+            .filter(|v| v.ra.addr() == *addr)
+            .map(|v| v.va)
+            .collect()
+    }
+
+    // Removes all clients and their virtual addresses, at shutdown.
+    pub fn clear(&mut self) {
+        for (va, v) in self.va_map.drain() {
+            self.events.push(VipEvent::Gone(va, v.ra.addr()));
+        }
+        self.ra_map.clear();
+    }
+
+    // Takes the vip events since the last call.
+    pub fn take_events(&mut self) -> Vec<VipEvent> {
+        mem::take(&mut self.events)
+    }
+
+    // Retrieves or adds a real address to the map, None if a new one is not admitted.
+    pub fn get_or_add_ra(&mut self, addr: &SocketAddr) -> Option<&RefRA> {
+        if !self.ra_map.contains_key(addr) && !self.admit_ra(addr) {
+            return None;
+        }
+
+        let ra = self
+            .ra_map
             .entry(*addr)
             .and_modify(|v| v.recv())
             .or_insert_with(|| {
                 debug!("New client [{:?}]", addr);
                 RefRA::new(*addr)
-            })
+            });
+        Some(ra)
+    }
+
+    // Makes room for a new real address under the per source IP and total limits.
+    fn admit_ra(&mut self, addr: &SocketAddr) -> bool {
+        let Admission {
+            max_clients,
+            max_clients_per_ip,
+            policy,
+            idle,
+            ..
+        } = self.admission;
+
+        let limits = [
+            (max_clients_per_ip, "max clients per ip", Some(addr.ip())),
+            (max_clients, "max clients", None),
+        ];
+        for (max, reason, ip) in limits {
+            let Some(max) = max else { continue };
+            loop {
+                let ras = self
+                    .ra_map
+                    .values()
+                    .filter(|v| ip.is_none_or(|ip| v.addr().ip() == ip));
+                if ras.clone().count() < max {
+                    break;
+                }
+
+                let oldest = ras
+                    .min_by_key(|v| v.last_recv())
+                    .filter(|v| policy == AdmissionPolicy::Evict && v.last_recv().elapsed() >= idle)
+                    .map(|v| v.addr());
+                match oldest {
+                    Some(oldest) => {
+                        self.events.push(VipEvent::Evict(None, oldest, reason));
+                        self.remove_ra(&oldest);
+                    }
+                    None => {
+                        self.events.push(VipEvent::Reject(None, *addr, reason));
+                        return false;
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    // Makes room for a new virtual address under the total limit.
+    fn admit_va(&mut self, va: IpAddr, addr: &SocketAddr) -> bool {
+        let Some(max) = self.admission.max_vips else {
+            return true;
+        };
+
+        while self.va_map.len() >= max {
+            let oldest = self
+                .va_map
+                .values()
+                .min_by_key(|v| v.last_recv)
+                .filter(|v| {
+                    self.admission.policy == AdmissionPolicy::Evict
+                        && v.last_recv.elapsed() >= self.admission.idle
+                })
+                .map(|v| (v.va, v.ra.addr()));
+            match oldest {
+                Some((oldest, at)) => {
+                    debug!("Recycle vip [{:?}] at [{:}]", oldest, at);
+                    self.va_map.remove(&oldest);
+                    self.events
+                        .push(VipEvent::Evict(Some(oldest), at, "max vips"));
+                    self.events.push(VipEvent::Gone(oldest, at));
+                }
+                None => {
+                    self.events
+                        .push(VipEvent::Reject(Some(va), *addr, "max vips"));
+                    return false;
+                }
+            }
+        }
+
+        true
     }
 
     // Adds or updates a virtual address.
@@ -114,6 +355,21 @@ impl RouteTable {
             return None;
         }
 
+        // a bound vip only moves to the same client
+        if let Some(v) = self.va_map.get(&va)
+            && self.vip_policy == VipPolicy::Bind
+            && v.ra.addr() != ra.addr()
+            && !v.ra.is_same_client(&ra)
+        {
+            self.events.push(VipEvent::Conflict(va, ra.addr()));
+            return None;
+        }
+
+        if !self.va_map.contains_key(&va) && !self.admit_va(va, &ra.addr()) {
+            return None;
+        }
+
+        let events = &mut self.events;
         let va = self
             .va_map
             .entry(va)
@@ -121,25 +377,51 @@ impl RouteTable {
                 v.last_recv = Instant::now();
                 if v.ra.addr() != ra.addr() {
                     info!("Change vip [{:?}] to [{:?}]", va, ra.addr());
+                    events.push(VipEvent::Gone(va, v.ra.addr()));
+                    events.push(VipEvent::New(va, ra.addr()));
                     v.ra = ra.clone();
                 }
             })
             .or_insert_with(|| {
                 info!("New vip [{:?}] at [{:?}]", va, ra.addr());
+                events.push(VipEvent::New(va, ra.addr()));
                 VirtualAddr::new(va, ra)
             });
 
         Some(va)
     }
 
-    // Retrieves a route for a virtual address.
-    pub fn get_route(&mut self, va: &IpAddr) -> Option<&VirtualAddr> {
-        //https://github.com/rust-lang/rfcs/blob/master/text/2094-nll.md#problem-case-3-conditional-control-flow-across-functions
-        if self.va_map.contains_key(va) {
-            return self.va_map.get(va);
+    // Retrieves the vip of the client at `addr` a source address belongs to, the
+    // address itself if announced by the client, or the gateway routing it.
+    pub fn source_vip(&mut self, va: &IpAddr, addr: &SocketAddr) -> Option<IpAddr> {
+        let owned = |va: &IpAddr| self.va_map.get(va).is_some_and(|v| v.ra.addr() == *addr);
+
+        let vip = if owned(va) {
+            *va
+        } else {
+            self.vt_routes
+                .matches(va)
+                .iter()
+                .find_map(|routes| routes.iter().map(|(gw, _)| *gw).find(|gw| owned(gw)))?
+        };
+        if let Some(v) = self.va_map.get_mut(&vip) {
+            v.last_recv = Instant::now();
         }
+        Some(vip)
+    }
+
+    // Checks if a virtual address is in a network routed to a gateway.
+    pub fn is_routed(&self, va: &IpAddr) -> bool {
+        !self.vt_routes.matches(va).is_empty()
+    }
 
-        self.get_rt_route(va)
+    // Retrieves the client of a virtual address, or of the gateway routing it,
+    // as the vip of the client and its real address.
+    pub fn get_route(&self, va: &IpAddr) -> Option<(IpAddr, RefRA)> {
+        match self.va_map.get(va) {
+            Some(v) => Some((v.va, v.ra.clone())),
+            None => self.get_rt_route(va),
+        }
     }
 
     #[allow(dead_code)]
@@ -161,27 +443,27 @@ impl RouteTable {
         }
     }
 
-    // Retrieves a route table route for a virtual address.
-    pub fn get_rt_route(&mut self, va: &IpAddr) -> Option<&VirtualAddr> {
-        let mut gw_ra: Option<RefRA> = None;
-        for (net, gw) in &self.vt_routes {
-            if net.contains(va) {
-                gw_ra = self.va_map.get(gw).map(|va| va.ra.clone());
-                if gw_ra.is_some() {
-                    break;
-                }
-            }
-        }
-
-        gw_ra.and_then(move |ra| self.add_or_update_va(*va, ra))
+    // Retrieves the gateway of the most specific route of a virtual address, the
+    // reachable one of the lowest metric. Less specific routes are used if no
+    // gateway is reachable.
+    pub fn get_rt_route(&self, va: &IpAddr) -> Option<(IpAddr, RefRA)> {
+        self.vt_routes.matches(va).into_iter().find_map(|routes| {
+            routes
+                .iter()
+                .filter_map(|(gw, metric)| self.va_map.get(gw).map(|v| (metric, v)))
+                .min_by_key(|(metric, _)| *metric)
+                .map(|(_, v)| (v.va, v.ra.clone()))
+        })
     }
 
     // Prunes outdated entries from the route table.
     pub fn prune(&mut self, timeout: Duration) {
         let now = Instant::now();
+        let events = &mut self.events;
         self.va_map.retain(|_, v| {
             if now.duration_since(v.last_recv) > timeout {
                 debug!("Recycle vip [{:?}] at [{:}]", v.va, v.ra.addr());
+                events.push(VipEvent::Gone(v.va, v.ra.addr()));
                 false
             } else {
                 true
@@ -197,11 +479,113 @@ impl RouteTable {
         });
     }
 }
+pub type Mac = [u8; 6];
+
+fn format_mac(mac: &Mac) -> String {
+    mac.iter()
+        .map(|v| format!("{:02x}", v))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+// Where a MAC address is, behind the local tap interface or at a client.
+#[derive(Clone)]
+pub enum Port {
+    Local,
+    Remote(RefRA),
+}
+
+impl Port {
+    fn addr(&self) -> Option<SocketAddr> {
+        match self {
+            Port::Local => None,
+            Port::Remote(ra) => Some(ra.addr()),
+        }
+    }
+}
+
+struct MacEntry {
+    port: Port,
+    last_recv: Instant,
+}
+
+// MAC addresses learned from the source of the frames, used in TAP mode.
+#[derive(Default)]
+pub struct MacTable {
+    map: HashMap<Mac, MacEntry>,
+}
+
+impl MacTable {
+    // Learns the port of a source address.
+    pub fn learn(&mut self, mac: Mac, port: Port) {
+        // a group address is never a source
+        if mac[0] & 1 != 0 {
+            return;
+        }
+
+        self.map
+            .entry(mac)
+            .and_modify(|v| {
+                v.last_recv = Instant::now();
+                if v.port.addr() != port.addr() {
+                    debug!("Move mac [{}] to [{:?}]", format_mac(&mac), port.addr());
+                    v.port = port.clone();
+                }
+            })
+            .or_insert_with(|| {
+                debug!("New mac [{}] at [{:?}]", format_mac(&mac), port.addr());
+                MacEntry {
+                    port,
+                    last_recv: Instant::now(),
+                }
+            });
+    }
+
+    // Retrieves the port of a unicast address, None if the frame is to be flooded.
+    pub fn get(&self, mac: &Mac) -> Option<&Port> {
+        self.map.get(mac).map(|v| &v.port)
+    }
+
+    // Removes the addresses at a real address.
+    pub fn remove_ra(&mut self, addr: &SocketAddr) {
+        self.map.retain(|_, v| v.port.addr() != Some(*addr));
+    }
+
+    // Prunes outdated entries.
+    pub fn prune(&mut self, timeout: Duration) {
+        let now = Instant::now();
+        self.map
+            .retain(|_, v| now.duration_since(v.last_recv) <= timeout);
+    }
+}
+
+impl Display for MacTable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        writeln!(f, "macs:")?;
+        let mut macs = self.map.iter().collect::<Vec<_>>();
+        macs.sort_by_key(|v| v.0);
+        for (mac, v) in macs {
+            writeln!(
+                f,
+                "{:<17} @ {:<50} {:>20} ago",
+                format_mac(mac),
+                v.port
+                    .addr()
+                    .map_or_else(|| "local".to_owned(), |v| v.to_string()),
+                pretty_duration(&v.last_recv.elapsed())
+            )?;
+        }
+        Ok(())
+    }
+}
+
 impl Display for RouteTable {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         writeln!(f, "routes:")?;
-        for r in &self.vt_routes {
-            writeln!(f, "{:} @ {:}", r.0, r.1)?;
+        for (net, routes) in self.vt_routes.iter() {
+            for (gw, metric) in routes {
+                writeln!(f, "{:} @ {:} metric {:}", net, gw, metric)?;
+            }
         }
         writeln!(f, "clients:")?;
         let mut cs = self.va_map.values().collect::<Vec<_>>();
@@ -218,3 +602,100 @@ impl Display for RouteTable {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    #[test]
+    fn test() {
+        let mut rt = RouteTable::default();
+        rt.set_vip_policy(VipPolicy::Bind);
+        let va: IpAddr = "10.0.0.2".parse().unwrap();
+        let owner = rt
+            .get_or_add_ra(&"1.1.1.1:1000".parse().unwrap())
+            .unwrap()
+            .clone();
+        owner.set_client_id(1);
+        assert!(rt.add_or_update_va(va, owner).is_some());
+
+        let other = rt
+            .get_or_add_ra(&"2.2.2.2:1000".parse().unwrap())
+            .unwrap()
+            .clone();
+        assert!(rt.add_or_update_va(va, other.clone()).is_none());
+        other.set_client_id(2);
+        assert!(rt.add_or_update_va(va, other).is_none());
+
+        // the owner roams to a new address
+        let roamed = rt
+            .get_or_add_ra(&"3.3.3.3:1000".parse().unwrap())
+            .unwrap()
+            .clone();
+        roamed.set_client_id(1);
+        assert_eq!(
+            rt.add_or_update_va(va, roamed).map(|v| v.ra.addr()),
+            Some("3.3.3.3:1000".parse().unwrap())
+        );
+
+        let events = rt.take_events();
+        assert!(matches!(events[1], VipEvent::Conflict(..)));
+        assert_eq!(events.len(), 5);
+    }
+
+    #[test]
+    fn test_admission() {
+        let mut rt = RouteTable::default();
+        rt.set_admission(Admission {
+            max_clients: Some(3),
+            max_clients_per_ip: Some(2),
+            max_vips: Some(1),
+            policy: AdmissionPolicy::Reject,
+            idle: Duration::ZERO,
+        });
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        assert!(rt.get_or_add_ra(&addr("1.1.1.1:1000")).is_some());
+        assert!(rt.get_or_add_ra(&addr("1.1.1.1:1001")).is_some());
+        assert!(rt.get_or_add_ra(&addr("1.1.1.1:1002")).is_none());
+        assert!(rt.get_or_add_ra(&addr("2.2.2.2:1000")).is_some());
+        assert!(rt.get_or_add_ra(&addr("3.3.3.3:1000")).is_none());
+        // known clients are still admitted
+        let ra = rt.get_or_add_ra(&addr("1.1.1.1:1000")).unwrap().clone();
+        assert!(rt
+            .add_or_update_va("10.0.0.2".parse().unwrap(), ra.clone())
+            .is_some());
+        assert!(rt
+            .add_or_update_va("10.0.0.3".parse().unwrap(), ra)
+            .is_none());
+        let events = rt.take_events();
+        assert!(matches!(
+            events[0],
+            VipEvent::Reject(None, _, "max clients per ip")
+        ));
+        assert!(matches!(
+            events[1],
+            VipEvent::Reject(None, _, "max clients")
+        ));
+        assert!(matches!(
+            events[3],
+            VipEvent::Reject(Some(_), _, "max vips")
+        ));
+
+        // only the idle ones are evicted
+        rt.admission.policy = AdmissionPolicy::Evict;
+        rt.admission.idle = Duration::from_secs(60);
+        assert!(rt.get_or_add_ra(&addr("1.1.1.1:1002")).is_none());
+        rt.take_events();
+        rt.admission.idle = Duration::ZERO;
+        assert!(rt.get_or_add_ra(&addr("1.1.1.1:1002")).is_some());
+        assert!(!rt.ra_map.contains_key(&addr("1.1.1.1:1001")));
+        assert!(rt.get_or_add_ra(&addr("3.3.3.3:1000")).is_some());
+        assert_eq!(rt.ra_map.len(), 3);
+        let events = rt.take_events();
+        assert!(matches!(
+            events[0],
+            VipEvent::Evict(None, _, "max clients per ip")
+        ));
+        assert!(matches!(events[1], VipEvent::Evict(None, _, "max clients")));
+    }
+}
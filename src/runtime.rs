@@ -1,20 +1,32 @@
 use crate::default_socket_configure;
 use crate::SocketConfigure;
 
+use crate::dns::Resolver;
+use crate::nat::Masquerade;
+use crate::netconf::{self, Netlink};
 #[cfg(feature = "holepunch")]
 use crate::socket::DummySocket;
 use crate::Error;
 use crate::{default_socket_factory, Config, Socket, SocketFactory};
+use log::warn;
 use std::os::fd::OwnedFd;
 use std::os::unix::net::UnixListener;
 use std::rc::Rc;
 
+/// Loads a fresh config, used to reload the configuration at runtime.
+pub type ConfigLoader = dyn Fn() -> Result<Config, Error>;
+
 pub struct Runtime {
     pub(crate) tun_fd: OwnedFd,
+    pub(crate) tun_name: Option<String>,
     pub(crate) control_fd: Option<UnixListener>,
     pub(crate) exit_signal: Option<OwnedFd>,
+    pub(crate) reload_signal: Option<OwnedFd>,
+    pub(crate) config_loader: Option<Box<ConfigLoader>>,
     pub(crate) socket: Option<Box<Socket>>,
     pub(crate) socket_factory: Option<Box<dyn SocketFactory>>,
+    pub(crate) resolver: Option<Box<dyn Resolver>>,
+    pub(crate) masquerade: Option<Masquerade>,
 }
 impl Runtime {
     pub fn with_socket(&mut self, s: Box<Socket>) -> &mut Self {
@@ -25,16 +37,91 @@ impl Runtime {
     pub fn socket(&self) -> Option<&Socket> {
         self.socket.as_deref()
     }
+
+    pub(crate) fn load_config(&self) -> Result<Config, Error> {
+        let loader = self
+            .config_loader
+            .as_ref()
+            .ok_or_else(|| Error::Other("config loader not set".into()))?;
+        loader()
+    }
+
+    // Applies changes of the attached routes to the tun interface.
+    pub(crate) fn update_routes(&self, old: &Config, new: &Config) -> Result<(), Error> {
+        let dev = match self.tun_name {
+            Some(ref dev) => dev,
+            None => return Ok(()),
+        };
+
+        let old_routes = old.tun_routes(dev)?;
+        let new_routes = new.tun_routes(dev)?;
+
+        // the interface index is resolved in the namespace of the caller
+        netconf::in_netns(new.netns.as_deref(), || {
+            let nl = Netlink::new()?;
+            for route in old_routes.iter().filter(|r| !new_routes.contains(r)) {
+                let _ = nl.del_route(route).inspect_err(|e| warn!("{}", e));
+            }
+
+            for route in new_routes.iter().filter(|r| !old_routes.contains(r)) {
+                let _ = nl.add_route(route).inspect_err(|e| warn!("{}", e));
+            }
+
+            Ok(())
+        })
+    }
+
+    // Removes the rules, routes and addresses of the tun interface.
+    pub(crate) fn deconfig_tun(&self, config: &Config) -> Result<(), Error> {
+        match self.tun_name {
+            Some(ref dev) => deconfig_tun(config, dev),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Removes the rules, routes and addresses of the config from the tun interface
+/// `dev`, in the namespace of the config.
+pub fn deconfig_tun(config: &Config, dev: &str) -> Result<(), Error> {
+    netconf::in_netns(config.netns.as_deref(), || {
+        let nl = Netlink::new()?;
+        for rule in config.tun_rules()? {
+            let _ = nl.del_rule(&rule).inspect_err(|e| warn!("{}", e));
+        }
+
+        for route in config.tun_routes(dev)? {
+            let _ = nl.del_route(&route).inspect_err(|e| warn!("{}", e));
+        }
+
+        if let Some(addr4) = config.loc_tun_in {
+            let _ = nl
+                .del_addr(addr4.into(), dev)
+                .inspect_err(|e| warn!("{}", e));
+        }
+
+        if let Some(addr6) = config.loc_tun_in6 {
+            let _ = nl
+                .del_addr(addr6.into(), dev)
+                .inspect_err(|e| warn!("{}", e));
+        }
+
+        Ok(())
+    })
 }
 
 pub struct RuntimeBuilder {
     config: Rc<Config>,
     tun_fd: Option<OwnedFd>,
+    tun_name: Option<String>,
     control_fd: Option<UnixListener>,
     exit_signal: Option<OwnedFd>,
+    reload_signal: Option<OwnedFd>,
+    config_loader: Option<Box<ConfigLoader>>,
     socket: Option<Box<Socket>>,
     socket_factory: Option<Box<dyn SocketFactory>>,
     socket_configure: Option<Box<dyn SocketConfigure>>,
+    resolver: Option<Box<dyn Resolver>>,
+    masquerade: Option<Masquerade>,
 }
 
 impl RuntimeBuilder {
@@ -42,11 +129,16 @@ impl RuntimeBuilder {
         Self {
             config,
             tun_fd: None,
+            tun_name: None,
             control_fd: None,
             exit_signal: None,
+            reload_signal: None,
+            config_loader: None,
             socket: None,
             socket_factory: None,
             socket_configure: None,
+            resolver: None,
+            masquerade: None,
         }
     }
     pub fn build(mut self) -> Result<Runtime, Error> {
@@ -103,10 +195,15 @@ impl RuntimeBuilder {
 
         Ok(Runtime {
             tun_fd: self.tun_fd.expect("tun fd not set"),
+            tun_name: self.tun_name.take(),
             control_fd: self.control_fd.take(),
             exit_signal: self.exit_signal.take(),
+            reload_signal: self.reload_signal.take(),
+            config_loader: self.config_loader.take(),
             socket,
             socket_factory: Some(socket_factory),
+            resolver: self.resolver.take(),
+            masquerade: self.masquerade.take(),
         })
     }
 }
@@ -122,6 +219,12 @@ impl RuntimeBuilder {
         self
     }
 
+    /// Interface name of the tun fd, required to update routes at runtime.
+    pub fn with_tun_name(&mut self, name: String) -> &mut Self {
+        self.tun_name = Some(name);
+        self
+    }
+
     pub fn with_socket_factory(&mut self, f: Box<dyn SocketFactory>) -> &mut Self {
         self.socket_factory = Some(f);
         self
@@ -141,4 +244,27 @@ impl RuntimeBuilder {
         self.exit_signal = Some(exit_signal);
         self
     }
+
+    /// The config is reloaded each time the fd becomes readable, e.g. a signalfd of SIGHUP.
+    pub fn with_reload_signal(&mut self, reload_signal: OwnedFd) -> &mut Self {
+        self.reload_signal = Some(reload_signal);
+        self
+    }
+
+    /// Applies the DNS configuration pushed by the server, client only.
+    pub fn with_resolver(&mut self, resolver: Box<dyn Resolver>) -> &mut Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Installed masquerade, removed when the tunnel shuts down, server only.
+    pub fn with_masquerade(&mut self, masquerade: Masquerade) -> &mut Self {
+        self.masquerade = Some(masquerade);
+        self
+    }
+
+    pub fn with_config_loader(&mut self, loader: Box<ConfigLoader>) -> &mut Self {
+        self.config_loader = Some(loader);
+        self
+    }
 }
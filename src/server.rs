@@ -1,46 +1,55 @@
-use crate::msg::{EchoPacket, IpDataPacket};
-use crate::util::{dest_ip, source_ip};
+use crate::acl::{self, Action, Flow};
+use crate::msg::{EchoExt, EchoPacket, FragmentPacket, IpDataBuilder, IpDataPacket};
+use crate::util::{dest_ip, dest_mac, source_ip, source_mac};
 use crate::{
-    config::Config,
+    config::{ClientPolicy, Config, Diff, VipPolicy},
     error::Error,
+    fragment::Fragments,
+    hook,
     msg::{Builder, IpDataKind, MsgBuilder, MsgPacket, Op},
-    poll,
-    route::{RefRA, RouteTable},
+    mss, poll,
+    route::{Admission, MacTable, Port, RefRA, RouteTable, VipEvent},
+    snapshot::{self, Snapshot},
     socket::Socket,
+    stats::{DropReason, Fault, Faults, Stat},
     Runtime,
 };
 use log::{debug, info, trace, warn};
 use nix::unistd::{read, write};
-use size::Size;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
-use std::io::{Read, Write};
 use std::mem::MaybeUninit;
 use std::net::{IpAddr, SocketAddr};
 use std::os::fd::OwnedFd;
-use std::os::unix::io::FromRawFd;
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::os::unix::net::UnixStream;
 use std::rc::Rc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
-#[derive(Default)]
-pub struct Stat {
-    rx_bytes: u64,
-    tx_bytes: u64,
-}
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+const CONFLICT_LOG_INTERVAL: Duration = Duration::from_secs(10);
 
 pub struct Server {
     config: Rc<Config>,
     rt: Runtime,
     stats: RefCell<HashMap<IpAddr, Stat>>,
+    // all traffic since start, kept when clients are gone
+    total: RefCell<Stat>,
     route: RefCell<RouteTable>,
+    macs: RefCell<MacTable>,
+    denied: RefCell<HashMap<String, u64>>,
+    faults: Faults,
+    fragments: RefCell<Fragments>,
+    hooks: RefCell<hook::Children>,
+    started: bool,
+    last_conflict_log: Cell<Option<Instant>>,
+    suppressed_conflicts: Cell<u64>,
     last_bind_try: Option<Instant>,
     last_rebind: Option<Instant>,
     last_health: Option<Instant>,
+    last_save: Option<Instant>,
 }
 
 impl Server {
@@ -49,29 +58,250 @@ impl Server {
             config,
             rt,
             stats: Default::default(),
+            total: Default::default(),
             route: Default::default(),
+            macs: Default::default(),
+            denied: Default::default(),
+            faults: Default::default(),
+            fragments: Default::default(),
+            hooks: Default::default(),
+            started: false,
+            last_conflict_log: Default::default(),
+            suppressed_conflicts: Default::default(),
             last_bind_try: Some(Instant::now()), // Initialize to now to avoid immediate rebind
             last_rebind: None,
             last_health: None,
+            last_save: None,
         })
     }
 
     pub fn run(self) -> Result<()> {
-        for (net, gw) in &self.config.routes {
-            match gw {
-                Some(gw) => self.route.borrow_mut().add_route(*net, *gw),
-                None => return Err("route gw must be set in server mode!".into()),
-            }
-        }
+        let control_fd = self.rt.control_fd.as_ref().map(|v| v.as_raw_fd());
+        let exit_signal = self.rt.exit_signal.as_ref().map(|v| v.as_raw_fd());
+        let reload_signal = self.rt.reload_signal.as_ref().map(|v| v.as_raw_fd());
+        let name = self.rt.tun_name.clone().unwrap_or_default();
 
         poll::poll(
-            self.tun().as_raw_fd(),
-            self.rt.control_fd.as_ref().map(|v| v.as_raw_fd()),
-            self.rt.exit_signal.as_ref().map(|v| v.as_raw_fd()),
-            self,
+            &mut [(name, Box::new(self) as Box<dyn poll::Reactor>)],
+            control_fd,
+            exit_signal,
+            reload_signal,
         )
     }
 
+    fn check_routes(config: &Config) -> Result<()> {
+        if config.routes.iter().any(|(_, gw, _)| gw.is_none()) {
+            return Err("route gw must be set in server mode!".into());
+        }
+        Ok(())
+    }
+
+    // A burst below the mtu would drop every full sized packet.
+    fn check_limits(config: &Config) -> Result<()> {
+        let limits = [config.ingress_limit, config.egress_limit]
+            .into_iter()
+            .chain(config.client_limits.iter().flat_map(|(_, i, e)| [*i, *e]))
+            .flatten();
+        for limit in limits {
+            if let Some(burst) = limit.burst.filter(|v| *v < config.mtu as u64) {
+                return Err(
+                    format!("rate limit burst {} is below the mtu {}", burst, config.mtu).into(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // The filters and limits look into IP packets, the frames of a tap interface
+    // would pass them unchecked.
+    fn check_tap(config: &Config) -> Result<()> {
+        if !config.tap {
+            return Ok(());
+        }
+        let unsupported = [
+            (
+                "acl",
+                !config.acl.is_empty() || config.acl_default != Action::Allow,
+            ),
+            ("strict-source", config.strict_source),
+            (
+                "client-to-client",
+                config.client_to_client != ClientPolicy::Kernel || !config.client_groups.is_empty(),
+            ),
+            ("ingress-limit", config.ingress_limit.is_some()),
+            ("egress-limit", config.egress_limit.is_some()),
+            ("client-limit", !config.client_limits.is_empty()),
+            ("mss-clamp", config.mss_clamp.is_some()),
+        ];
+        match unsupported.iter().find(|(_, set)| *set) {
+            Some((name, _)) => Err(format!("{} is not supported in tap mode", name).into()),
+            None => Ok(()),
+        }
+    }
+
+    fn load_routes(&self) {
+        let mut route = self.route.borrow_mut();
+        route.clear_routes();
+        for (net, gw, metric) in &self.config.routes {
+            if let Some(gw) = gw {
+                route.add_route(*net, *gw, metric.unwrap_or_default());
+            }
+        }
+    }
+
+    // Saves the clients and their counters to the state file.
+    fn save_state(&mut self) -> Result<()> {
+        let Some(ref path) = self.config.state_file else {
+            return Ok(());
+        };
+        self.last_save = Some(Instant::now());
+
+        let stats = self.stats.borrow();
+        let clients = self
+            .route
+            .borrow()
+            .virtual_addrs()
+            .map(|v| snapshot::Client {
+                va: v.va,
+                addr: v.ra.addr(),
+                client_id: v.ra.client_id(),
+                idle: v.last_recv.elapsed(),
+                counters: stats.get(&v.va).map(Stat::counters).unwrap_or_default(),
+            })
+            .collect();
+        Snapshot::new(clients).save(path)?;
+        Ok(())
+    }
+
+    // Restores the clients saved by a previous run, those timed out since are skipped.
+    fn restore_state(&mut self) -> Result<()> {
+        let Some(ref path) = self.config.state_file else {
+            return Ok(());
+        };
+        let Some(snapshot) = Snapshot::load(path)? else {
+            return Ok(());
+        };
+
+        let route = self.route.get_mut();
+        let stats = self.stats.get_mut();
+        let mut count = 0;
+        for c in snapshot.clients {
+            // an idle time before the clock starts, e.g. right after boot, can't
+            // be kept and the client would get a full timeout again
+            let last_seen = match Instant::now().checked_sub(c.idle) {
+                Some(v) if c.idle <= self.config.client_timeout => v,
+                _ => continue,
+            };
+            route.restore_va(c.va, c.addr, c.client_id, last_seen);
+            stats.insert(c.va, Stat::from_counters(&c.counters, last_seen));
+            count += 1;
+        }
+        info!("restore {} vips from {}", count, path.display());
+        Ok(())
+    }
+
+    // Applies the vip policy and admission limits to the route table.
+    fn load_policies(&self) {
+        let mut route = self.route.borrow_mut();
+        route.set_vip_policy(self.config.vip_policy);
+        route.set_admission(Admission {
+            max_clients: self.config.max_clients,
+            max_clients_per_ip: self.config.max_clients_per_ip,
+            max_vips: self.config.max_vips,
+            policy: self.config.admission,
+            idle: self.config.client_timeout / 2,
+        });
+    }
+
+    fn reload_config(&mut self) -> Result<Diff> {
+        let config = self.rt.load_config()?;
+        Self::check_routes(&config)?;
+
+        let (config, diff) = self.config.reload(config);
+        Self::check_tap(&config)?;
+        Self::check_limits(&config)?;
+        self.rt.update_routes(&self.config, &config)?;
+        self.config = Rc::new(config);
+        self.load_routes();
+        self.load_policies();
+
+        Ok(diff)
+    }
+
+    // Runs the client hooks of the vips appeared or gone.
+    fn run_client_hooks(&self) {
+        let events = self.route.borrow_mut().take_events();
+        let ifname = self.rt.tun_name.as_deref().unwrap_or_default();
+        for event in events {
+            let (cmd, name, vip, addr) = match event {
+                VipEvent::Conflict(vip, addr) => {
+                    self.deny("vip conflict".into());
+                    self.log_conflict(vip, addr);
+                    continue;
+                }
+                VipEvent::Reject(vip, addr, reason) => {
+                    match vip {
+                        Some(vip) => debug!(
+                            "admission: reject vip [{:}] at [{:}], {}",
+                            vip, addr, reason
+                        ),
+                        None => debug!("admission: reject client [{:}], {}", addr, reason),
+                    }
+                    self.deny(format!("rejected: {}", reason));
+                    continue;
+                }
+                VipEvent::Evict(vip, addr, reason) => {
+                    match vip {
+                        Some(vip) => {
+                            info!("admission: evict vip [{:}] at [{:}], {}", vip, addr, reason)
+                        }
+                        None => {
+                            info!("admission: evict client [{:}], {}", addr, reason);
+                            self.macs.borrow_mut().remove_ra(&addr);
+                        }
+                    }
+                    self.deny(format!("evicted: {}", reason));
+                    continue;
+                }
+                VipEvent::New(vip, addr) => {
+                    (&self.config.client_connect, "client-connect", vip, addr)
+                }
+                VipEvent::Gone(vip, addr) => (
+                    &self.config.client_disconnect,
+                    "client-disconnect",
+                    vip,
+                    addr,
+                ),
+            };
+            if let Some(cmd) = cmd {
+                let env = hook::client_env(&self.config, ifname, vip, addr);
+                self.hooks.borrow_mut().spawn(cmd, name, env);
+            }
+        }
+    }
+
+    // Logs a rejected vip claim, once per interval with the number of the
+    // others since the last one.
+    fn log_conflict(&self, vip: IpAddr, addr: SocketAddr) {
+        let now = Instant::now();
+        if let Some(last) = self.last_conflict_log.get()
+            && now.duration_since(last) < CONFLICT_LOG_INTERVAL
+        {
+            self.suppressed_conflicts
+                .set(self.suppressed_conflicts.get() + 1);
+            return;
+        }
+        self.last_conflict_log.set(Some(now));
+
+        match self.suppressed_conflicts.replace(0) {
+            0 => warn!("security: reject vip [{:}] claimed by [{:}]", vip, addr),
+            n => warn!(
+                "security: reject vip [{:}] claimed by [{:}], {} more rejected since the last",
+                vip, addr, n
+            ),
+        }
+    }
+
     fn socket(&self) -> &Socket {
         self.rt.socket.as_deref().expect("socket must be available")
     }
@@ -80,58 +310,310 @@ impl Server {
         &self.rt.tun_fd
     }
 
+    // Counts a dropped packet by the reason.
+    fn deny(&self, reason: String) {
+        *self.denied.borrow_mut().entry(reason).or_default() += 1;
+    }
+
+    // Sends to a client, a failure is counted.
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) {
+        if let Err(e) = self.socket().send_to(buf, addr) {
+            trace!("send to {:} fail. {:?}", addr, e);
+            self.faults.add(Fault::Send);
+        }
+    }
+
+    // Writes to the tun interface, a failure is counted.
+    fn write_tun(&self, pkt: &[u8]) {
+        if let Err(e) = write(self.tun(), pkt) {
+            trace!("write tun fail. {:?}", e);
+            self.faults.add(Fault::TunWrite);
+        }
+    }
+
+    // Counts on the total and the stats of the vips.
+    fn count(&self, vips: impl IntoIterator<Item = IpAddr>, f: impl Fn(&mut Stat)) {
+        f(&mut self.total.borrow_mut());
+        let mut stats = self.stats.borrow_mut();
+        for vip in vips {
+            f(stats.entry(vip).or_default());
+        }
+    }
+
+    // Applies the acl rules to a packet.
+    fn is_allowed(&self, pkt: &[u8]) -> bool {
+        if self.config.acl.is_empty() && self.config.acl_default == Action::Allow {
+            return true;
+        }
+
+        let flow = match Flow::parse(pkt) {
+            Ok(flow) => flow,
+            Err(_) => return false,
+        };
+        match acl::check(&self.config.acl, self.config.acl_default, &flow) {
+            (Action::Allow, _) => true,
+            (Action::Deny, rule) => {
+                trace!("deny {:?}", flow);
+                self.deny(rule.map_or_else(|| "default".into(), |v| v.to_string()));
+                false
+            }
+        }
+    }
+
     fn forward_remote(&self, kind: IpDataKind, pkt: &[u8]) -> Result<()> {
+        let clamped = self.clamp_mss(kind, pkt);
+        let pkt = clamped.as_deref().unwrap_or(pkt);
         let dst = dest_ip(pkt)?;
-        let mut route = self.route.borrow_mut();
-        let va = route
-            .get_route(&dst)
-            .ok_or_else(|| crate::error::Error::NoRoute(dst.to_string()))?;
+        let client = self.route.borrow().get_route(&dst);
+        if !self.is_allowed(pkt) {
+            self.count(client.map(|(vip, _)| vip), |s| s.dropped(DropReason::Acl));
+            return Ok(());
+        }
 
-        let mut stats = self.stats.borrow_mut();
-        let stat = stats.entry(dst).or_default();
-        stat.tx_bytes += pkt.len() as u64;
+        if self.config.is_flooded(&dst) {
+            return self.flood(kind, pkt, None);
+        }
+
+        let Some((vip, ra)) = client else {
+            self.count(None, |s| s.dropped(DropReason::NoRoute));
+            return Err(Error::NoRoute(dst.to_string()).into());
+        };
+
+        if let (_, Some(limit)) = self.config.client_limits(&vip)
+            && !ra.egress(&limit, pkt.len())
+        {
+            trace!("drop {:} to {:}, egress limit", dst, vip);
+            self.count(Some(vip), |s| s.dropped(DropReason::EgressLimit));
+            return Ok(());
+        }
+        self.count(Some(vip), |s| s.tx(pkt.len()));
 
-        let msg = self.new_msg(&va.ra)?.ip_data()?.kind(kind)?.payload(pkt)?;
-        let dst = va.ra.addr();
+        self.send_data(&ra, kind, pkt)
+    }
+
+    // Sends an ip data message, split in fragments if it is larger than `--fragment`.
+    fn send_data(&self, ra: &RefRA, kind: IpDataKind, pkt: &[u8]) -> Result<()> {
+        let msg = self
+            .new_msg(ra)?
+            .ip_data()?
+            .kind(kind)?
+            .payload(pkt)?
+            .build()?;
+        let Some(size) = self.config.fragment.filter(|v| msg.len() > *v as usize) else {
+            self.send_to(&msg, ra.addr());
+            return Ok(());
+        };
 
-        // ignore failure
-        let _ = self.socket().send_to(&msg.build()?, dst);
+        let body = IpDataBuilder::default().kind(kind)?.payload(pkt)?.build()?;
+        let (id, pieces) = self.fragments.borrow_mut().split(&body, size)?;
+        for (index, piece) in pieces.iter().enumerate() {
+            let msg = self
+                .new_msg(ra)?
+                .fragment()?
+                .id(id)?
+                .index(index as u8, pieces.len() as u8)?
+                .payload(piece)?;
+            self.send_to(&msg.build()?, ra.addr());
+        }
 
         Ok(())
     }
 
-    fn forward_local(&self, ra: &SocketAddr, pkt: &[u8]) -> Result<()> {
-        let src = source_ip(pkt)?;
-        let ra = self.route.borrow_mut().get_or_add_ra(ra).clone();
-        if self.route.borrow_mut().add_or_update_va(src, ra).is_none() {
+    // A copy of `pkt` with the MSS of a SYN lowered to `--mss-clamp`, if needed.
+    fn clamp_mss(&self, kind: IpDataKind, pkt: &[u8]) -> Option<Vec<u8>> {
+        let clamp = self.config.mss_clamp?;
+        mss::clamp(pkt, clamp.mss(self.config.mtu, kind == IpDataKind::V6))
+    }
+
+    // Replicates a broadcast or multicast packet to all clients but `except`.
+    fn flood(&self, kind: IpDataKind, pkt: &[u8], except: Option<&SocketAddr>) -> Result<()> {
+        let route = self.route.borrow();
+        for ra in route.real_addrs() {
+            if Some(&ra.addr()) == except {
+                continue;
+            }
+
+            // charged to each client as a unicast packet would be
+            let vips = route.vips_at(&ra.addr());
+            if let Some((_, Some(limit))) = vips.first().map(|v| self.config.client_limits(v))
+                && !ra.egress(&limit, pkt.len())
+            {
+                trace!("drop flooded packet to {:}, egress limit", ra.addr());
+                self.count(vips, |s| s.dropped(DropReason::EgressLimit));
+                continue;
+            }
+
+            self.send_data(ra, kind, pkt)?;
+        }
+
+        Ok(())
+    }
+
+    // Retrieves the vip of the client a source address is charged to, None if
+    // the packet is dropped. Only a source outside the routed networks is learned
+    // from the data, and only if a vip may roam, a bound one is claimed by echo.
+    fn source_vip(&self, src: &IpAddr, ra: &RefRA) -> Option<IpAddr> {
+        let addr = ra.addr();
+        let mut route = self.route.borrow_mut();
+        if let Some(vip) = route.source_vip(src, &addr) {
+            return Some(vip);
+        }
+
+        if self.config.strict_source {
+            debug!("drop spoofed src {:} from {:}", src, addr);
+            self.deny("spoofed source".into());
+            return None;
+        }
+        if self.config.vip_policy == VipPolicy::Bind || route.is_routed(src) {
+            trace!("drop unowned src {:} from {:}", src, addr);
+            self.deny("unowned source".into());
+            return None;
+        }
+        if route.add_or_update_va(*src, ra.clone()).is_none() {
             debug!("unknown src {:}", src);
+            return None;
+        }
+        Some(*src)
+    }
+
+    fn forward_local(&self, addr: &SocketAddr, kind: IpDataKind, pkt: &[u8]) -> Result<()> {
+        let clamped = self.clamp_mss(kind, pkt);
+        let pkt = clamped.as_deref().unwrap_or(pkt);
+        let src = source_ip(pkt)?;
+        let Some(ra) = self.route.borrow_mut().get_or_add_ra(addr).cloned() else {
+            return Ok(());
+        };
+        let Some(vip) = self.source_vip(&src, &ra) else {
+            return Ok(());
+        };
+
+        if !self.is_allowed(pkt) {
+            self.count(Some(vip), |s| s.dropped(DropReason::Acl));
             return Ok(());
         }
 
-        let mut stats = self.stats.borrow_mut();
-        let stat = stats.entry(src).or_default();
-        stat.rx_bytes += pkt.len() as u64;
+        if let (Some(limit), _) = self.config.client_limits(&vip)
+            && !ra.ingress(&limit, pkt.len())
+        {
+            trace!("drop {:} from {:}, ingress limit", src, addr);
+            self.count(Some(vip), |s| s.dropped(DropReason::IngressLimit));
+            return Ok(());
+        }
+        self.count(Some(vip), |s| s.rx(pkt.len()));
+
+        let dst = dest_ip(pkt)?;
+        let policy = self.config.client_policy(&src);
+        let local = self.config.loc_tun_in.map(|v| IpAddr::from(v.addr())) == Some(dst)
+            || self.config.loc_tun_in6.map(|v| IpAddr::from(v.addr())) == Some(dst);
+        let peer = self
+            .route
+            .borrow()
+            .get_route(&dst)
+            .map(|(_, ra)| ra.addr())
+            .filter(|v| v != addr);
+        if peer.is_some() && !local {
+            if policy == ClientPolicy::Isolate
+                || self.config.client_policy(&dst) == ClientPolicy::Isolate
+            {
+                debug!("drop {:} to {:}, client isolated", src, dst);
+                return Ok(());
+            }
+            if policy == ClientPolicy::Hairpin {
+                return self.forward_remote(kind, pkt);
+            }
+        }
+
+        self.write_tun(pkt);
 
-        // ignore failure
-        let _ = write(self.tun(), pkt);
+        // other clients on the link receive it as well
+        if self.config.is_flooded(&dst) && policy != ClientPolicy::Isolate {
+            self.flood(kind, pkt, Some(addr))?;
+        }
+
+        Ok(())
+    }
+
+    // Sends a frame from the tap interface to the client of its destination,
+    // floods it to all clients if the destination is a group or unknown address.
+    fn forward_frame_remote(&self, frame: &[u8]) -> Result<()> {
+        let (dst, src) = (dest_mac(frame)?, source_mac(frame)?);
+        let mut macs = self.macs.borrow_mut();
+        macs.learn(src, Port::Local);
+
+        match macs.get(&dst) {
+            Some(Port::Remote(ra)) => self.send_frame(ra, frame)?,
+            Some(Port::Local) => {}
+            None => {
+                for ra in self.route.borrow().real_addrs() {
+                    self.send_frame(ra, frame)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Bridges a frame from a client to the tap interface or another client, a
+    // group or unknown destination is flooded to both.
+    fn forward_frame_local(&self, ra: &SocketAddr, frame: &[u8]) -> Result<()> {
+        let (dst, src) = (dest_mac(frame)?, source_mac(frame)?);
+        let Some(ra) = self.route.borrow_mut().get_or_add_ra(ra).cloned() else {
+            return Ok(());
+        };
+        let mut macs = self.macs.borrow_mut();
+        macs.learn(src, Port::Remote(ra.clone()));
+
+        match macs.get(&dst) {
+            Some(Port::Remote(to)) => {
+                if to.addr() != ra.addr() {
+                    self.send_frame(to, frame)?;
+                }
+            }
+            Some(Port::Local) => {
+                self.write_tun(frame);
+            }
+            None => {
+                self.write_tun(frame);
+                for to in self.route.borrow().real_addrs() {
+                    if to.addr() != ra.addr() {
+                        self.send_frame(to, frame)?;
+                    }
+                }
+            }
+        }
 
         Ok(())
     }
 
+    fn send_frame(&self, ra: &RefRA, frame: &[u8]) -> Result<()> {
+        self.send_data(ra, IpDataKind::Ether, frame)
+    }
+
     fn handle_echo_req<T: AsRef<[u8]>>(&self, src: SocketAddr, pkt: EchoPacket<T>) -> Result<()> {
-        let ra = self.route.borrow_mut().get_or_add_ra(&src).clone();
+        let Some(ra) = self.route.borrow_mut().get_or_add_ra(&src).cloned() else {
+            return Ok(());
+        };
+        for ext in pkt.exts() {
+            if let EchoExt::ClientId(id) = ext {
+                ra.set_client_id(id);
+            }
+        }
 
+        // no ack if a vip is bound to another client
         let (va4, va6) = pkt.ip_addr()?;
-        if !va4.is_unspecified() {
-            self.route
-                .borrow_mut()
-                .add_or_update_va(va4.into(), ra.clone());
-        }
-        if !va6.is_unspecified() {
-            self.route
-                .borrow_mut()
-                .add_or_update_va(va6.into(), ra.clone());
+        let mut route = self.route.borrow_mut();
+        let mut stats = self.stats.borrow_mut();
+        for va in [IpAddr::from(va4), IpAddr::from(va6)] {
+            if va.is_unspecified() {
+                continue;
+            }
+            if route.add_or_update_va(va, ra.clone()).is_none() {
+                return Ok(());
+            }
+            stats.entry(va).or_default().last_seen = Instant::now();
         }
+        drop(stats);
+        drop(route);
 
         let mut msg = self.new_msg(&ra)?.echo_ack()?.id(pkt.id()?)?;
 
@@ -143,8 +625,74 @@ impl Server {
             msg = msg.ipv6_addr(addr6.addr())?;
         }
 
-        // ignore failure
-        let _ = self.socket().send_to(&msg.build()?, src);
+        for net in &self.config.push_route {
+            msg = msg.ext(&EchoExt::Route(*net))?;
+        }
+
+        for addr in &self.config.push_dns {
+            msg = msg.ext(&EchoExt::Dns(*addr))?;
+        }
+
+        for domain in &self.config.push_domain {
+            msg = msg.ext(&EchoExt::DnsSearch(domain.clone()))?;
+        }
+
+        self.send_to(&msg.build()?, src);
+
+        Ok(())
+    }
+
+    fn handle_ip_data(&self, src: SocketAddr, data: IpDataPacket<&[u8]>) -> Result<()> {
+        match data.kind() {
+            Ok(IpDataKind::Ether) if self.config.tap => {
+                self.forward_frame_local(&src, data.payload()?)?
+            }
+            Ok(kind @ (IpDataKind::V4 | IpDataKind::V6)) if !self.config.tap => {
+                self.forward_local(&src, kind, data.payload()?)?
+            }
+            kind => debug!("unexpected ip data {:?}", kind),
+        }
+
+        Ok(())
+    }
+
+    fn handle_msg(&self, src: SocketAddr, msg: MsgPacket<&[u8]>) -> Result<()> {
+        match msg.op() {
+            Ok(Op::IpData) => {
+                self.handle_ip_data(src, IpDataPacket::new(msg.payload()?)?)?;
+            }
+            Ok(Op::Fragment) => {
+                let frag = FragmentPacket::new(msg.payload()?)?;
+                let body = self.fragments.borrow_mut().add(src, &frag);
+                match body {
+                    Ok(Some(body)) => self.handle_ip_data(src, IpDataPacket::new(&body[..])?)?,
+                    Ok(None) => {}
+                    Err(e) => {
+                        debug!("drop fragment from {:}. {:?}", src, e);
+                        self.faults.add(Fault::Reassembly);
+                    }
+                }
+            }
+            Ok(Op::EchoReq) => {
+                let echo = EchoPacket::new(msg.payload()?)?;
+                debug!("received echo req {:?}", echo.ip_addr()?);
+                self.handle_echo_req(src, echo)?;
+            }
+            Ok(Op::Disconnect) => {
+                info!("client [{:}] disconnected", src);
+                let mut route = self.route.borrow_mut();
+                route.remove_ra(&src);
+                self.macs.borrow_mut().remove_ra(&src);
+                self.stats.borrow_mut().retain(|k, _| route.contains(k));
+            }
+            Err(e) => {
+                debug!("unknown op from {:}. {:?}", src, e);
+                self.faults.add(Fault::UnknownOp);
+            }
+            _ => {
+                debug!("unexpected msg {:?}", msg.op());
+            }
+        }
 
         Ok(())
     }
@@ -191,19 +739,29 @@ impl Display for Server {
         }
 
         write!(f, "{:}", self.route.borrow())?;
+        if self.config.tap {
+            write!(f, "{:}", self.macs.borrow())?;
+        }
+
+        let denied = self.denied.borrow();
+        if !denied.is_empty() {
+            writeln!(f, "denied:")?;
+            let mut denied = denied.iter().collect::<Vec<_>>();
+            denied.sort();
+            for (reason, count) in denied {
+                writeln!(f, "{:<50} {:>10}", reason, count)?;
+            }
+        }
+
+        write!(f, "{:}", self.faults)?;
 
         writeln!(f, "stats:")?;
+        writeln!(f, "{:<15} {:}", "total", self.total.borrow())?;
         let stats = self.stats.borrow();
         let mut stat = stats.iter().collect::<Vec<_>>();
         stat.sort_by(|a, b| a.0.partial_cmp(b.0).unwrap());
         for s in stat {
-            writeln!(
-                f,
-                "{:<15} rx: {:>10}\t tx: {:>10}",
-                s.0,
-                Size::from_bytes(s.1.rx_bytes).to_string(),
-                Size::from_bytes(s.1.tx_bytes).to_string(),
-            )?;
+            writeln!(f, "{:<15} {:}", s.0, s.1)?;
         }
 
         Ok(())
@@ -211,19 +769,52 @@ impl Display for Server {
 }
 
 impl poll::Reactor for Server {
+    fn tun_fd(&self) -> RawFd {
+        self.tun().as_raw_fd()
+    }
+
     fn socket_fd(&self) -> Option<RawFd> {
         Some(self.socket().as_raw_fd())
     }
 
+    fn start(&mut self) -> Result<()> {
+        Self::check_routes(&self.config)?;
+        Self::check_tap(&self.config)?;
+        Self::check_limits(&self.config)?;
+        self.load_routes();
+        self.load_policies();
+        // ignore failure, the clients come back with their next keepalive
+        let _ = self
+            .restore_state()
+            .inspect_err(|e| warn!("restore state fail. {}", e));
+        self.started = true;
+        Ok(())
+    }
+
     fn tunnel_recv(&self) -> Result<()> {
-        let mut buf = unsafe { MaybeUninit::assume_init(MaybeUninit::<[u8; 1500]>::uninit()) };
+        let mut buf =
+            unsafe { MaybeUninit::assume_init(MaybeUninit::<[u8; poll::BUFFER_SIZE]>::uninit()) };
         let size = read(self.tun().as_raw_fd(), &mut buf)?;
+        // a packet filling the buffer may be truncated
+        if size == buf.len() {
+            self.faults.add(Fault::Oversized);
+            return Ok(());
+        }
+
+        if self.config.tap {
+            //ignore result
+            let _ = self
+                .forward_frame_remote(&buf[..size])
+                .inspect_err(|e| debug!("forward remote fail. {:?}", e));
+            return Ok(());
+        }
 
         let kind = match buf[0] >> 4 {
             4 => IpDataKind::V4,
             6 => IpDataKind::V6,
             _ => {
                 warn!("[INPUT]invalid packet");
+                self.count(None, |s| s.dropped(DropReason::Invalid));
                 return Ok(());
             }
         };
@@ -237,7 +828,8 @@ impl poll::Reactor for Server {
     }
 
     fn network_recv(&self) -> Result<()> {
-        let mut buf = unsafe { MaybeUninit::assume_init(MaybeUninit::<[u8; 1500]>::uninit()) };
+        let mut buf =
+            unsafe { MaybeUninit::assume_init(MaybeUninit::<[u8; poll::BUFFER_SIZE]>::uninit()) };
         let (size, src) = match self.socket().recv_from(&mut buf) {
             Ok((size, src)) => (size, src),
             Err(e) => {
@@ -247,25 +839,35 @@ impl poll::Reactor for Server {
         };
 
         trace!("receive from {:}, size {:}", src, size);
+        if size == buf.len() {
+            debug!("oversized packet from {:}", src);
+            self.faults.add(Fault::Oversized);
+            return Ok(());
+        }
+
         match MsgPacket::<&[u8]>::with_cryptor(&mut buf[..size], self.config.cryptor()) {
-            Ok(msg) => match msg.op() {
-                Ok(Op::IpData) => {
-                    self.forward_local(&src, IpDataPacket::new(msg.payload()?)?.payload()?)?;
+            Ok(msg) => {
+                if let Err(e) = self.handle_msg(src, msg) {
+                    debug!("invalid msg from {:}. {:?}", src, e);
+                    let vips = self.route.borrow().vips_at(&src);
+                    self.count(vips, |s| s.dropped(DropReason::Invalid));
                 }
-                Ok(Op::EchoReq) => {
-                    let echo = EchoPacket::new(msg.payload()?)?;
-                    debug!("received echo req {:?}", echo.ip_addr()?);
-                    self.handle_echo_req(src, echo)?;
-                }
-                _ => {
-                    debug!("unexpected msg {:?}", msg.op());
-                }
-            },
-            _ => {
-                trace!("invalid packet")
+            }
+            Err(e) => {
+                trace!("invalid packet. {:?}", e);
+                let fault = Fault::of(&e);
+                self.faults.add(fault);
+                let reason = match fault {
+                    Fault::Short => DropReason::Invalid,
+                    _ => DropReason::Decrypt,
+                };
+                let vips = self.route.borrow().vips_at(&src);
+                self.count(vips, |s| s.dropped(reason));
             }
         }
 
+        self.run_client_hooks();
+
         Ok(())
     }
 
@@ -319,32 +921,84 @@ impl poll::Reactor for Server {
             self.last_health = Some(last_health);
         }
 
-        let Self { route, stats, .. } = self;
+        for _ in 0..self.fragments.get_mut().expire() {
+            self.faults.add(Fault::Reassembly);
+        }
+
+        let Self {
+            route, stats, macs, ..
+        } = self;
 
         route.get_mut().prune(self.config.client_timeout);
+        macs.get_mut().prune(self.config.client_timeout);
         stats.get_mut().retain(|k, _| route.borrow().contains(k));
+
+        if self
+            .last_save
+            .is_none_or(|v| v.elapsed() > STATE_SAVE_INTERVAL)
+        {
+            let _ = self
+                .save_state()
+                .inspect_err(|e| warn!("save state fail. {}", e));
+        }
+
+        self.run_client_hooks();
+        self.hooks.get_mut().reap();
         Ok(())
     }
 
-    fn handle_control_connection(&mut self, fd: RawFd) -> Result<()> {
-        let mut us = unsafe { UnixStream::from_raw_fd(fd) };
-        let mut buf = [0u8; 64];
-
-        // First try to read from the socket in case it's a command
-        if let Ok(n) = us.read(&mut buf) {
-            let resp = if let Ok(s) = std::str::from_utf8(&buf[..n]) {
-                if s.trim() == "show-info" {
-                    self.to_string()
-                } else {
-                    format!("Unknown command: {}\n", s.trim())
+    fn handle_control(&mut self, cmd: &str) -> String {
+        match cmd {
+            "show-info" => self.to_string(),
+            "reload" => match self.reload_config() {
+                Ok(diff) => diff.to_string(),
+                Err(e) => {
+                    warn!("Failed to reload config: {}", e);
+                    format!("Failed to reload config: {}\n", e)
                 }
-            } else {
-                "Invalid UTF-8 sequence\n".to_string()
-            };
-
-            let _ = us.write(resp.as_bytes());
+            },
+            _ => format!("Unknown command: {}\n", cmd),
         }
+    }
 
+    fn shutdown(&mut self) -> Result<()> {
+        info!("shutting down...");
+        // a tunnel shut down before it started keeps the state file
+        if self.started {
+            let _ = self
+                .save_state()
+                .inspect_err(|e| warn!("save state fail. {}", e));
+        }
+        for ra in self.route.borrow().real_addrs() {
+            // ignore failure
+            let _ = self
+                .socket()
+                .send_to(&self.new_msg(ra)?.disconnect()?.build()?, ra.addr());
+        }
+        // the clients are gone, their hooks finish before the interface does
+        self.route.get_mut().clear();
+        self.run_client_hooks();
+        self.hooks.get_mut().wait();
+        if let Some(mut masquerade) = self.rt.masquerade.take() {
+            // tried again when dropped
+            let _ = masquerade
+                .remove()
+                .inspect_err(|e| warn!("remove masquerade fail. {}", e));
+        }
+        self.rt.deconfig_tun(&self.config)?;
         Ok(())
     }
+
+    fn reload(&mut self) -> Result<()> {
+        match self.reload_config() {
+            Ok(diff) => {
+                info!("config reloaded, {}", diff.to_string().trim_end());
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Failed to reload config: {}", e);
+                Err(e)
+            }
+        }
+    }
 }
@@ -0,0 +1,137 @@
+//! Token bucket rate limits of the server data path.
+//!
+//! A limit reads `rate[:burst]` in bytes per second and bytes, the `k`, `m` and
+//! `g` suffixes are powers of 1000.
+
+use crate::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::time::Instant;
+
+// smallest default burst, so that a full sized packet always fits
+const MIN_BURST: u64 = 64 * 1024;
+
+fn parse_bytes(s: &str) -> Option<u64> {
+    let (n, unit) = match s.char_indices().last()? {
+        (i, 'k' | 'K') => (&s[..i], 1_000),
+        (i, 'm' | 'M') => (&s[..i], 1_000_000),
+        (i, 'g' | 'G') => (&s[..i], 1_000_000_000),
+        _ => (s, 1),
+    };
+    n.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn fmt_bytes(n: u64) -> String {
+    match n {
+        n if n >= 1_000_000_000 && n % 1_000_000_000 == 0 => format!("{}g", n / 1_000_000_000),
+        n if n >= 1_000_000 && n % 1_000_000 == 0 => format!("{}m", n / 1_000_000),
+        n if n >= 1_000 && n % 1_000 == 0 => format!("{}k", n / 1_000),
+        n => n.to_string(),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limit {
+    /// Bytes per second.
+    pub rate: u64,
+    /// Bytes sent at once after idling, a tenth of the rate by default, at least
+    /// `MIN_BURST`.
+    pub burst: Option<u64>,
+}
+
+impl Limit {
+    pub fn burst(&self) -> u64 {
+        self.burst.unwrap_or((self.rate / 10).max(MIN_BURST))
+    }
+}
+
+impl FromStr for Limit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidArg(format!("invalid rate limit {:?}", s));
+        let (rate, burst) = match s.split_once(':') {
+            Some((rate, burst)) => (rate, Some(parse_bytes(burst).ok_or_else(invalid)?)),
+            None => (s, None),
+        };
+        let rate = parse_bytes(rate).filter(|v| *v > 0).ok_or_else(invalid)?;
+
+        Ok(Self { rate, burst })
+    }
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", fmt_bytes(self.rate))?;
+        if let Some(burst) = self.burst {
+            write!(f, ":{}", fmt_bytes(burst))?;
+        }
+        Ok(())
+    }
+}
+
+/// Tokens of one direction of a client, the limit is given on each use so a
+/// reloaded one applies at once. The bucket starts full.
+#[derive(Clone, Default)]
+pub struct TokenBucket {
+    tokens: f64,
+    last: Option<Instant>,
+}
+
+impl TokenBucket {
+    /// Takes `len` bytes worth of tokens, false if there are not enough.
+    pub fn take(&mut self, limit: &Limit, len: usize) -> bool {
+        self.take_at(limit, len, Instant::now())
+    }
+
+    fn take_at(&mut self, limit: &Limit, len: usize, now: Instant) -> bool {
+        let burst = limit.burst() as f64;
+        self.tokens = match self.last {
+            Some(last) => {
+                let refill = now.saturating_duration_since(last).as_secs_f64() * limit.rate as f64;
+                (self.tokens + refill).min(burst)
+            }
+            None => burst,
+        };
+        self.last = Some(now);
+
+        if self.tokens < len as f64 {
+            return false;
+        }
+        self.tokens -= len as f64;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let limit: Limit = "1m".parse().unwrap();
+        assert_eq!(limit.rate, 1_000_000);
+        assert_eq!(limit.burst(), 100_000);
+        assert_eq!("2M:1500".parse::<Limit>().unwrap().to_string(), "2m:1500");
+        assert_eq!("10k".parse::<Limit>().unwrap().burst(), MIN_BURST);
+        assert!("0".parse::<Limit>().is_err());
+        assert!("1x".parse::<Limit>().is_err());
+        assert!("1m:".parse::<Limit>().is_err());
+
+        let limit: Limit = "10k:3000".parse().unwrap();
+        let mut bucket = TokenBucket::default();
+        let now = Instant::now();
+        assert!(bucket.take_at(&limit, 1500, now));
+        assert!(bucket.take_at(&limit, 1500, now));
+        assert!(!bucket.take_at(&limit, 1500, now));
+        // 100ms refill 1000 bytes
+        let now = now + Duration::from_millis(100);
+        assert!(!bucket.take_at(&limit, 1500, now));
+        assert!(bucket.take_at(&limit, 1000, now));
+        // never above the burst
+        let now = now + Duration::from_secs(10);
+        assert!(bucket.take_at(&limit, 3000, now));
+        assert!(!bucket.take_at(&limit, 1, now));
+    }
+}
@@ -0,0 +1,185 @@
+//! Clients of a server saved to a state file and restored after a restart.
+//!
+//! The file has a `saved <unix time>` line and a line per virtual address,
+//! `<vip> <real addr> <idle secs> <client id|-> [<counter>=<value>...]`.
+
+use crate::error::Error;
+use crate::util::with_suffix;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, PartialEq)]
+pub struct Client {
+    pub va: IpAddr,
+    pub addr: SocketAddr,
+    pub client_id: Option<u64>,
+    pub idle: Duration,
+    pub counters: Vec<(String, u64)>,
+}
+
+impl Display for Client {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {} ", self.va, self.addr, self.idle.as_secs())?;
+        match self.client_id {
+            Some(id) => write!(f, "{:x}", id)?,
+            None => write!(f, "-")?,
+        }
+        for (name, value) in &self.counters {
+            write!(f, " {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Client {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidArg(format!("invalid state {:?}", s));
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let [va, addr, idle, id, ref counters @ ..] = words[..] else {
+            return Err(invalid());
+        };
+        let num = |v: &str| v.parse::<u64>().map_err(|_| invalid());
+        let counters = counters
+            .iter()
+            .map(|v| {
+                let (name, value) = v.split_once('=').ok_or_else(invalid)?;
+                Ok((name.to_owned(), num(value)?))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Self {
+            va: va.parse().map_err(|_| invalid())?,
+            addr: addr.parse().map_err(|_| invalid())?,
+            client_id: match id {
+                "-" => None,
+                id => Some(u64::from_str_radix(id, 16).map_err(|_| invalid())?),
+            },
+            idle: Duration::from_secs(num(idle)?),
+            counters,
+        })
+    }
+}
+
+pub struct Snapshot {
+    pub saved: SystemTime,
+    pub clients: Vec<Client>,
+}
+
+impl Snapshot {
+    pub fn new(clients: Vec<Client>) -> Self {
+        Self {
+            saved: SystemTime::now(),
+            clients,
+        }
+    }
+
+    /// Writes the file atomically, through a temporary file renamed over it once
+    /// on disk. Only the owner may read it, it lists the clients' addresses.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let tmp = with_suffix(path, ".tmp");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)?;
+        file.write_all(self.to_string().as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Reads the file, None if there is none. The time since it was saved is
+    /// added to the idle time of the clients.
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        let s = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut snapshot: Self = s.parse()?;
+        let elapsed = snapshot.saved.elapsed().unwrap_or_default();
+        for client in snapshot.clients.iter_mut() {
+            client.idle += elapsed;
+        }
+        Ok(Some(snapshot))
+    }
+}
+
+impl Display for Snapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let saved = self.saved.duration_since(UNIX_EPOCH).unwrap_or_default();
+        writeln!(f, "saved {}", saved.as_secs())?;
+        for client in &self.clients {
+            writeln!(f, "{}", client)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut snapshot = Self {
+            saved: SystemTime::now(),
+            clients: Vec::new(),
+        };
+        for line in s.lines().map(str::trim).filter(|v| !v.is_empty()) {
+            match line.strip_prefix("saved ") {
+                Some(saved) => {
+                    let saved = saved
+                        .parse()
+                        .map_err(|_| Error::InvalidArg(format!("invalid state {:?}", line)))?;
+                    snapshot.saved = UNIX_EPOCH + Duration::from_secs(saved);
+                }
+                None => snapshot.clients.push(line.parse()?),
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    #[test]
+    fn test() {
+        let client = Client {
+            va: "10.0.0.2".parse().unwrap(),
+            addr: "1.2.3.4:5678".parse().unwrap(),
+            client_id: Some(0xabcd),
+            idle: Duration::from_secs(3),
+            counters: vec![("rx-bytes".to_owned(), 100), ("drop-acl".to_owned(), 2)],
+        };
+        assert_eq!(
+            client.to_string(),
+            "10.0.0.2 1.2.3.4:5678 3 abcd rx-bytes=100 drop-acl=2"
+        );
+
+        let path = std::env::temp_dir().join(format!("minivtun.state.{}", std::process::id()));
+        assert!(Snapshot::load(&path).unwrap().is_none());
+        let mut snapshot = Snapshot::new(vec![client]);
+        snapshot.saved -= Duration::from_secs(10);
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.clients[0].idle >= Duration::from_secs(13));
+        assert_eq!(loaded.clients[0].client_id, Some(0xabcd));
+        assert_eq!(loaded.clients[0].counters[1].1, 2);
+
+        assert!("10.0.0.2 1.2.3.4:5678 3".parse::<Snapshot>().is_err());
+        assert!("saved now".parse::<Snapshot>().is_err());
+        assert!("10.0.0.2 1.2.3.4:5678 3 - rx".parse::<Snapshot>().is_err());
+    }
+}
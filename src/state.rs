@@ -11,6 +11,7 @@ pub struct State {
     pub rx_bytes: u64,
     pub tx_bytes: u64,
     pub connect_attempts: u32,
+    pub client_id: u64,
 }
 
 impl Default for State {
@@ -25,6 +26,7 @@ impl Default for State {
             rx_bytes: 0,
             tx_bytes: 0,
             connect_attempts: 0,
+            client_id: thread_rng().next_u64(),
         }
     }
 }
@@ -0,0 +1,260 @@
+//! Traffic counters of the server, per client and in total, and failures of
+//! the data path on both ends.
+
+use crate::error::Error;
+use crate::util::pretty_duration;
+use size::Size;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// Why a packet was dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DropReason {
+    NoRoute,
+    Decrypt,
+    Invalid,
+    Acl,
+    IngressLimit,
+    EgressLimit,
+}
+
+impl DropReason {
+    const ALL: [Self; 6] = [
+        Self::NoRoute,
+        Self::Decrypt,
+        Self::Invalid,
+        Self::Acl,
+        Self::IngressLimit,
+        Self::EgressLimit,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NoRoute => "no-route",
+            Self::Decrypt => "decrypt",
+            Self::Invalid => "invalid",
+            Self::Acl => "acl",
+            Self::IngressLimit => "ingress-limit",
+            Self::EgressLimit => "egress-limit",
+        }
+    }
+}
+
+pub struct Stat {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub drops: BTreeMap<DropReason, u64>,
+    pub first_seen: Instant,
+    // last packet from the client
+    pub last_seen: Instant,
+}
+
+impl Default for Stat {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            rx_packets: 0,
+            rx_bytes: 0,
+            tx_packets: 0,
+            tx_bytes: 0,
+            drops: BTreeMap::new(),
+            first_seen: now,
+            last_seen: now,
+        }
+    }
+}
+
+impl Stat {
+    pub fn rx(&mut self, len: usize) {
+        self.rx_packets += 1;
+        self.rx_bytes += len as u64;
+        self.last_seen = Instant::now();
+    }
+
+    pub fn tx(&mut self, len: usize) {
+        self.tx_packets += 1;
+        self.tx_bytes += len as u64;
+    }
+
+    pub fn dropped(&mut self, reason: DropReason) {
+        *self.drops.entry(reason).or_default() += 1;
+    }
+
+    /// Counters by name as saved in the state file, `first-seen` is in seconds ago.
+    pub fn counters(&self) -> Vec<(String, u64)> {
+        let mut counters = vec![
+            ("rx-packets".to_owned(), self.rx_packets),
+            ("rx-bytes".to_owned(), self.rx_bytes),
+            ("tx-packets".to_owned(), self.tx_packets),
+            ("tx-bytes".to_owned(), self.tx_bytes),
+            ("first-seen".to_owned(), self.first_seen.elapsed().as_secs()),
+        ];
+        for (reason, count) in &self.drops {
+            counters.push((format!("drop-{}", reason.name()), *count));
+        }
+        counters
+    }
+
+    /// Restores the counters saved by `counters`, unknown ones are ignored.
+    pub fn from_counters(counters: &[(String, u64)], last_seen: Instant) -> Self {
+        let mut stat = Self {
+            last_seen,
+            ..Default::default()
+        };
+        for (name, value) in counters {
+            match name.as_str() {
+                "rx-packets" => stat.rx_packets = *value,
+                "rx-bytes" => stat.rx_bytes = *value,
+                "tx-packets" => stat.tx_packets = *value,
+                "tx-bytes" => stat.tx_bytes = *value,
+                "first-seen" => {
+                    stat.first_seen = Instant::now()
+                        .checked_sub(Duration::from_secs(*value))
+                        .unwrap_or(last_seen)
+                }
+                name => {
+                    let reason = name
+                        .strip_prefix("drop-")
+                        .and_then(|v| DropReason::ALL.into_iter().find(|r| r.name() == v));
+                    if let Some(reason) = reason {
+                        stat.drops.insert(reason, *value);
+                    }
+                }
+            }
+        }
+        stat
+    }
+}
+
+impl Display for Stat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rx: {:>10} {:>8} pkts\t tx: {:>10} {:>8} pkts\t first: {:>10} ago\t last: {:>10} ago",
+            Size::from_bytes(self.rx_bytes).to_string(),
+            self.rx_packets,
+            Size::from_bytes(self.tx_bytes).to_string(),
+            self.tx_packets,
+            pretty_duration(&self.first_seen.elapsed()),
+            pretty_duration(&self.last_seen.elapsed()),
+        )?;
+        if !self.drops.is_empty() {
+            let drops = self
+                .drops
+                .iter()
+                .map(|(reason, count)| format!("{} {}", reason.name(), count))
+                .collect::<Vec<_>>();
+            write!(f, "\t dropped: {}", drops.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A failure on the data path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Fault {
+    Send,
+    TunWrite,
+    Decrypt,
+    AuthKey,
+    UnknownOp,
+    Invalid,
+    Short,
+    Oversized,
+    Reassembly,
+}
+
+impl Fault {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Send => "send",
+            Self::TunWrite => "tun-write",
+            Self::Decrypt => "decrypt",
+            Self::AuthKey => "auth-key",
+            Self::UnknownOp => "unknown-op",
+            Self::Invalid => "invalid",
+            Self::Short => "short",
+            Self::Oversized => "oversized",
+            Self::Reassembly => "reassembly",
+        }
+    }
+
+    /// Classifies a failure to open a received datagram.
+    pub fn of(e: &Error) -> Self {
+        match e {
+            Error::ShortPacket => Self::Short,
+            Error::AuthFail => Self::AuthKey,
+            _ => Self::Decrypt,
+        }
+    }
+}
+
+/// Counts of the data path failures, kept for the lifetime of the tunnel.
+#[derive(Default)]
+pub struct Faults(RefCell<BTreeMap<Fault, u64>>);
+
+impl Faults {
+    pub fn add(&self, fault: Fault) {
+        *self.0.borrow_mut().entry(fault).or_default() += 1;
+    }
+}
+
+impl Display for Faults {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let faults = self.0.borrow();
+        if faults.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "errors:")?;
+        for (fault, count) in faults.iter() {
+            writeln!(f, "{:<15} {:>10}", fault.name(), count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    #[test]
+    fn test() {
+        let mut stat = Stat::default();
+        stat.rx(100);
+        stat.rx(50);
+        stat.tx(20);
+        stat.dropped(DropReason::Acl);
+        stat.dropped(DropReason::Acl);
+        stat.dropped(DropReason::NoRoute);
+        assert_eq!(stat.rx_packets, 2);
+        assert!(stat.to_string().ends_with("dropped: no-route 1, acl 2"));
+
+        let counters = stat.counters();
+        assert_eq!(counters[1], ("rx-bytes".to_owned(), 150));
+        assert_eq!(counters[6], ("drop-acl".to_owned(), 2));
+
+        let mut counters = counters;
+        counters.push(("unknown".to_owned(), 1));
+        let restored = Stat::from_counters(&counters, Instant::now());
+        assert_eq!(restored.rx_bytes, 150);
+        assert_eq!(restored.tx_packets, 1);
+        assert_eq!(restored.drops, stat.drops);
+
+        let faults = Faults::default();
+        assert_eq!(faults.to_string(), "");
+        faults.add(Fault::of(&Error::AuthFail));
+        faults.add(Fault::of(&Error::ShortPacket));
+        faults.add(Fault::Short);
+        assert_eq!(
+            faults.to_string(),
+            format!(
+                "errors:\n{:<15} {:>10}\n{:<15} {:>10}\n",
+                "auth-key", 1, "short", 2
+            )
+        );
+    }
+}
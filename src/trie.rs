@@ -0,0 +1,133 @@
+//! Binary prefix trie for longest prefix match of IPv4 and IPv6 networks.
+
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+struct Node<T> {
+    children: [Option<Box<Node<T>>>; 2],
+    values: Vec<T>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Self {
+            children: [None, None],
+            values: Vec::new(),
+        }
+    }
+}
+
+// Address as 128 bits from the most significant one, and its length.
+fn bits(addr: &IpAddr) -> (u128, u8) {
+    match addr {
+        IpAddr::V4(addr) => ((u32::from(*addr) as u128) << 96, 32),
+        IpAddr::V6(addr) => (u128::from(*addr), 128),
+    }
+}
+
+fn bit(bits: u128, i: u8) -> usize {
+    (bits >> (127 - i as u32)) as usize & 1
+}
+
+/// Values keyed by networks, several values may share a network.
+pub struct PrefixTrie<T> {
+    v4: Node<T>,
+    v6: Node<T>,
+    nets: Vec<IpNet>,
+}
+
+impl<T> Default for PrefixTrie<T> {
+    fn default() -> Self {
+        Self {
+            v4: Default::default(),
+            v6: Default::default(),
+            nets: Vec::new(),
+        }
+    }
+}
+
+impl<T> PrefixTrie<T> {
+    fn root(&self, addr: &IpAddr) -> &Node<T> {
+        match addr {
+            IpAddr::V4(_) => &self.v4,
+            IpAddr::V6(_) => &self.v6,
+        }
+    }
+
+    pub fn insert(&mut self, net: IpNet, value: T) {
+        let mut node = match net {
+            IpNet::V4(_) => &mut self.v4,
+            IpNet::V6(_) => &mut self.v6,
+        };
+        let (bits, _) = bits(&net.network());
+        for i in 0..net.prefix_len() {
+            node = node.children[bit(bits, i)].get_or_insert_with(Default::default);
+        }
+        node.values.push(value);
+
+        if !self.nets.contains(&net) {
+            self.nets.push(net);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Default::default();
+    }
+
+    /// Values of the networks containing `addr`, the most specific first.
+    pub fn matches(&self, addr: &IpAddr) -> Vec<&[T]> {
+        let (bits, len) = bits(addr);
+        let mut node = self.root(addr);
+        let mut matches = vec![node.values.as_slice()];
+        for i in 0..len {
+            match node.children[bit(bits, i)] {
+                Some(ref child) => node = child,
+                None => break,
+            }
+            matches.push(node.values.as_slice());
+        }
+        matches.retain(|v| !v.is_empty());
+        matches.reverse();
+        matches
+    }
+
+    /// Networks in the order of insertion with their values.
+    pub fn iter(&self) -> impl Iterator<Item = (&IpNet, &[T])> {
+        self.nets.iter().map(|net| {
+            let (bits, _) = bits(&net.network());
+            let mut node = self.root(&net.network());
+            for i in 0..net.prefix_len() {
+                node = node.children[bit(bits, i)]
+                    .as_deref()
+                    .expect("inserted network");
+            }
+            (net, node.values.as_slice())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::*;
+
+    #[test]
+    fn test() {
+        let mut trie = PrefixTrie::default();
+        trie.insert("0.0.0.0/0".parse().unwrap(), 0);
+        trie.insert("10.0.0.0/8".parse().unwrap(), 8);
+        trie.insert("10.1.0.0/16".parse().unwrap(), 16);
+        trie.insert("10.1.0.0/16".parse().unwrap(), 17);
+        trie.insert("fd00::/8".parse().unwrap(), 6);
+
+        let matches = |addr: &str| trie.matches(&addr.parse().unwrap());
+        assert_eq!(matches("10.1.2.3"), [&[16, 17][..], &[8], &[0]]);
+        assert_eq!(matches("10.2.0.1"), [&[8][..], &[0]]);
+        assert_eq!(matches("192.168.0.1"), [&[0][..]]);
+        assert_eq!(matches("fd00::1"), [&[6][..]]);
+        assert!(matches("fe80::1").is_empty());
+
+        assert_eq!(trie.iter().count(), 4);
+        trie.clear();
+        assert!(trie.matches(&"10.1.2.3".parse().unwrap()).is_empty());
+    }
+}
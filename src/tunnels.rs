@@ -0,0 +1,58 @@
+use crate::poll::{self, Reactor};
+use crate::{Client, Server};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::net::UnixListener;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Runs several named tunnels in one event loop.
+///
+/// The control, exit and reload fds are shared by all tunnels, a control
+/// command is addressed to one tunnel by prefixing it with the tunnel name.
+#[derive(Default)]
+pub struct Tunnels {
+    reactors: Vec<(String, Box<dyn Reactor>)>,
+    control_fd: Option<UnixListener>,
+    exit_signal: Option<OwnedFd>,
+    reload_signal: Option<OwnedFd>,
+}
+
+impl Tunnels {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_client(&mut self, name: &str, client: Client) -> &mut Self {
+        self.reactors.push((name.to_owned(), Box::new(client)));
+        self
+    }
+
+    pub fn with_server(&mut self, name: &str, server: Server) -> &mut Self {
+        self.reactors.push((name.to_owned(), Box::new(server)));
+        self
+    }
+
+    pub fn with_control_fd(&mut self, fd: UnixListener) -> &mut Self {
+        self.control_fd = Some(fd);
+        self
+    }
+
+    pub fn with_exit_signal(&mut self, exit_signal: OwnedFd) -> &mut Self {
+        self.exit_signal = Some(exit_signal);
+        self
+    }
+
+    pub fn with_reload_signal(&mut self, reload_signal: OwnedFd) -> &mut Self {
+        self.reload_signal = Some(reload_signal);
+        self
+    }
+
+    pub fn run(mut self) -> Result<()> {
+        poll::poll(
+            &mut self.reactors,
+            self.control_fd.as_ref().map(|v| v.as_raw_fd()),
+            self.exit_signal.as_ref().map(|v| v.as_raw_fd()),
+            self.reload_signal.as_ref().map(|v| v.as_raw_fd()),
+        )
+    }
+}
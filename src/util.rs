@@ -1,6 +1,8 @@
 use crate::error::{Error, Result};
 use rand::Rng;
+use std::ffi::OsString;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
 use std::{convert::TryInto, net::IpAddr, time::Duration};
 
 /// Converts a byte slice to an IPv4 address
@@ -33,6 +35,22 @@ pub fn dest_ip(pkt: &[u8]) -> Result<IpAddr> {
     }
 }
 
+/// Extracts the destination MAC address from an Ethernet frame
+pub fn dest_mac(frame: &[u8]) -> Result<[u8; 6]> {
+    frame
+        .get(0..6)
+        .and_then(|v| v.try_into().ok())
+        .ok_or(Error::InvalidPacket)
+}
+
+/// Extracts the source MAC address from an Ethernet frame
+pub fn source_mac(frame: &[u8]) -> Result<[u8; 6]> {
+    frame
+        .get(6..12)
+        .and_then(|v| v.try_into().ok())
+        .ok_or(Error::InvalidPacket)
+}
+
 /// Builds a server address by choosing a random port within a specified range
 ///
 /// # Arguments
@@ -67,6 +85,12 @@ pub fn build_server_addr(addr: &str) -> String {
 
     gen_addr.unwrap_or_else(|| addr.to_owned())
 }
+// The path with `suffix` appended to its file name, an extension is kept.
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = OsString::from(path);
+    s.push(suffix);
+    s.into()
+}
 pub(crate) fn pretty_duration(duration: &Duration) -> String {
     pretty_duration::pretty_duration(&Duration::from_secs(duration.as_secs()), None)
 }